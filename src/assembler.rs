@@ -0,0 +1,491 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::instructions::Instruction;
+
+const RESERVED_NAMES: [&str; 9] = ["I", "[I]", "K", "DT", "ST", "F", "HF", "B", "R"];
+
+/// An error in the assembly source, at a 1-based line and column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+/// A piece of source text, along with its 1-based column.
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+enum Statement<'a> {
+    /// An instruction, which is long if it loads an address beyond 12 bits into I (F000 NNNN).
+    Instruction { mnemonic: Token<'a>, operands: Vec<Token<'a>>, long: bool },
+    /// DB - Bytes of data.
+    Bytes(Vec<Token<'a>>),
+    /// DW - Big-endian 16-bit words of data.
+    Words(Vec<Token<'a>>),
+}
+
+struct Line<'a> {
+    number: usize,
+    statement: Statement<'a>,
+}
+
+/// A `NAME = VALUE` constant, which is evaluated once all labels are known.
+struct Constant<'a> {
+    line: usize,
+    name: Token<'a>,
+    value: Token<'a>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(u8),
+    /// A range of registers, e.g. `V1 - V3`.
+    RegisterRange(u8, u8),
+    I,
+    IndirectI,
+    K,
+    DelayTimer,
+    SoundTimer,
+    Font,
+    LargeFont,
+    Bcd,
+    Flags,
+    Value(u32),
+}
+
+/// Assembles source text into a program to be loaded at the given address.
+///
+/// Each line holds an optional `label:`, followed by an instruction using the mnemonics of the `Instruction` display
+/// format (e.g. `SE V1, 0x23`), a `DB` or `DW` data directive, or a `NAME = VALUE` constant. Comments start with `;`.
+/// `LD I` uses the long XO-CHIP form for addresses beyond 12 bits.
+pub fn assemble(source: &str, start_address: u16) -> Result<Vec<u8>, AssemblyError> {
+    // First pass: assign addresses to labels, then evaluate constants, which may refer to labels further on. Whether
+    // `LD I` needs the long form is only known once the address it loads is, so the layout is repeated with the
+    // instructions found to be long until no more are.
+    let mut long_lines = HashSet::new();
+    let (symbols, lines) = loop {
+        let (mut symbols, constants, lines) = layout(source, start_address, &long_lines)?;
+        evaluate_constants(&constants, &mut symbols)?;
+        let grown: Vec<usize> = lines
+            .iter()
+            .filter(|line| matches!(&line.statement,
+                Statement::Instruction { mnemonic, operands, long: false } if loads_long_address(*mnemonic, operands, &symbols)))
+            .map(|line| line.number)
+            .collect();
+        if grown.is_empty() {
+            break (symbols, lines);
+        }
+        long_lines.extend(grown);
+    };
+
+    // Second pass: resolve symbols and encode.
+    let mut program = vec![];
+    for line in lines {
+        let error = |column: usize, message: String| AssemblyError { line: line.number, column, message };
+        match line.statement {
+            Statement::Bytes(values) => {
+                for value in values {
+                    let byte = parse_value(value, &symbols).and_then(|byte| check_range(byte, 0xFF));
+                    program.push(byte.map_err(|message| error(value.column, message))? as u8);
+                }
+            },
+            Statement::Words(values) => {
+                for value in values {
+                    let word = parse_value(value, &symbols).and_then(|word| check_range(word, 0xFFFF));
+                    program.extend_from_slice(&(word.map_err(|message| error(value.column, message))? as u16).to_be_bytes());
+                }
+            },
+            Statement::Instruction { mnemonic, operands, long } => {
+                let instruction = encode(mnemonic, &operands, long, &symbols).map_err(|(column, message)| error(column, message))?;
+                program.extend(instruction.encode_bytes());
+            },
+        }
+    }
+
+    Ok(program)
+}
+
+type Layout<'a> = (HashMap<String, u32>, Vec<Constant<'a>>, Vec<Line<'a>>);
+
+/// Parses the source into lines and constants, and assigns addresses to labels, given the lines of long instructions.
+fn layout<'a>(source: &'a str, start_address: u16, long_lines: &HashSet<usize>) -> Result<Layout<'a>, AssemblyError> {
+    let mut symbols = HashMap::new();
+    let mut constants: Vec<Constant> = vec![];
+    let mut lines = vec![];
+    let mut address = start_address as u32;
+    for (index, text) in source.lines().enumerate() {
+        let number = index + 1;
+        let error = |column: usize, message: String| AssemblyError { line: number, column, message };
+        let text = text.split(';').next().unwrap_or_default();
+        let mut tokens = tokenize(text);
+
+        if let Some(label) = tokens.first().and_then(|token| token.text.strip_suffix(':')) {
+            let column = tokens[0].column;
+            validate_name(label).map_err(|message| error(column, message))?;
+            if symbols.insert(label.to_string(), address).is_some() || constants.iter().any(|constant| constant.name.text == label) {
+                return Err(error(column, format!("Duplicate symbol: {}.", label)));
+            }
+            tokens.remove(0);
+        }
+
+        if tokens.len() >= 2 && tokens[1].text == "=" {
+            let name = tokens[0];
+            validate_name(name.text).map_err(|message| error(name.column, message))?;
+            let value = match tokens.get(2) {
+                Some(&value) if tokens.len() == 3 => value,
+                _ => return Err(error(name.column, "Expected a single value for the constant.".to_string())),
+            };
+            if symbols.contains_key(name.text) || constants.iter().any(|constant| constant.name.text == name.text) {
+                return Err(error(name.column, format!("Duplicate symbol: {}.", name.text)));
+            }
+            constants.push(Constant { line: number, name, value });
+            continue;
+        }
+
+        let mnemonic = match tokens.first() {
+            Some(&mnemonic) => mnemonic,
+            None => continue,
+        };
+        let operands = split_operands(text, &tokens[1..]);
+        let (statement, size) = match mnemonic.text.to_ascii_uppercase().as_str() {
+            "DB" => (Statement::Bytes(operands.clone()), operands.len() as u32),
+            "DW" => (Statement::Words(operands.clone()), operands.len() as u32 * 2),
+            _ => {
+                let long = long_lines.contains(&number) || loads_long_address(mnemonic, &operands, &symbols);
+                (Statement::Instruction { mnemonic, operands, long }, if long { 4 } else { 2 })
+            }
+        };
+        address += size;
+        if address > 0x10000 {
+            return Err(error(mnemonic.column, "Program exceeds 64 KiB.".to_string()));
+        }
+        lines.push(Line { number, statement });
+    }
+
+    Ok((symbols, constants, lines))
+}
+
+/// Adds the values of the constants to the symbols. Constants may refer to labels and to each other in any order, as
+/// long as they do not refer to themselves.
+fn evaluate_constants(constants: &[Constant], symbols: &mut HashMap<String, u32>) -> Result<(), AssemblyError> {
+    let mut pending: Vec<&Constant> = constants.iter().collect();
+    while !pending.is_empty() {
+        let pending_count = pending.len();
+        let mut deferred = vec![];
+        for &constant in &pending {
+            match parse_value(constant.value, symbols) {
+                Ok(value) => {
+                    symbols.insert(constant.name.text.to_string(), value);
+                },
+                Err(_) if pending.iter().any(|other| other.name.text == constant.value.text) => deferred.push(constant),
+                Err(message) => return Err(AssemblyError { line: constant.line, column: constant.value.column, message }),
+            }
+        }
+        if deferred.len() == pending_count {
+            let constant = deferred[0];
+            let message = format!("Circular definition of constant: {}.", constant.name.text);
+            return Err(AssemblyError { line: constant.line, column: constant.name.column, message });
+        }
+        pending = deferred;
+    }
+    Ok(())
+}
+
+/// Splits a line into whitespace separated tokens, keeping track of their columns.
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut start = None;
+    for (index, character) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (start, character.is_whitespace() || character == ',') {
+            (None, false) => start = Some(index),
+            (Some(token_start), true) => {
+                tokens.push(Token { text: &text[token_start .. index], column: token_start + 1 });
+                start = None;
+            },
+            _ => {},
+        }
+        if character == ',' {
+            tokens.push(Token { text: ",", column: index + 1 });
+        }
+    }
+    tokens
+}
+
+/// Groups the tokens following the mnemonic into comma separated operands, e.g. `V1 - V3`.
+fn split_operands<'a>(text: &'a str, tokens: &[Token<'a>]) -> Vec<Token<'a>> {
+    tokens
+        .split(|token| token.text == ",")
+        .filter_map(|group| {
+            let first = group.first()?;
+            let last = group.last()?;
+            Some(Token { text: &text[first.column - 1 .. last.column - 1 + last.text.len()], column: first.column })
+        })
+        .collect()
+}
+
+/// Whether the instruction is `LD I` with an address beyond 12 bits, as far as the symbols are known.
+fn loads_long_address(mnemonic: Token, operands: &[Token], symbols: &HashMap<String, u32>) -> bool {
+    match operands {
+        [register, value] if mnemonic.text.eq_ignore_ascii_case("LD") && register.text.eq_ignore_ascii_case("I") => {
+            parse_value(*value, symbols).is_ok_and(|address| address > 0xFFF)
+        },
+        _ => false,
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), String> {
+    let mut characters = name.chars();
+    let valid = characters.next().is_some_and(|character| character.is_ascii_alphabetic() || character == '_')
+        && characters.all(|character| character.is_ascii_alphanumeric() || character == '_');
+    if !valid {
+        return Err(format!("Invalid symbol name: {}.", name));
+    }
+    if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(name)) || parse_register(name).is_some() {
+        return Err(format!("Reserved symbol name: {}.", name));
+    }
+    Ok(())
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let register = text.strip_prefix('V').or_else(|| text.strip_prefix('v'))?;
+    if register.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(register, 16).ok()
+}
+
+/// Parses a number in decimal or, prefixed with 0x or 0b, in hexadecimal or binary, or looks up a symbol.
+fn parse_value(token: Token, symbols: &HashMap<String, u32>) -> Result<u32, String> {
+    let text = token.text;
+    let number = if let Some(hex) = text.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else if text.starts_with(|character: char| character.is_ascii_digit()) {
+        text.parse::<u32>().ok()
+    } else {
+        return symbols.get(text).copied().ok_or_else(|| format!("Undefined symbol: {}.", text));
+    };
+    number.ok_or_else(|| format!("Invalid number: {}.", text))
+}
+
+fn check_range(value: u32, max: u32) -> Result<u32, String> {
+    if value > max {
+        return Err(format!("Value out of range: 0x{:X} (maximum: 0x{:X}).", value, max));
+    }
+    Ok(value)
+}
+
+fn parse_operand(token: Token, symbols: &HashMap<String, u32>) -> Result<Operand, String> {
+    if let Some(register) = parse_register(token.text) {
+        return Ok(Operand::Register(register));
+    }
+    if let Some((first, last)) = token.text.split_once('-') {
+        if let (Some(first), Some(last)) = (parse_register(first.trim()), parse_register(last.trim())) {
+            return Ok(Operand::RegisterRange(first, last));
+        }
+    }
+    let operand = match token.text.to_ascii_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "K" => Operand::K,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "F" => Operand::Font,
+        "HF" => Operand::LargeFont,
+        "B" => Operand::Bcd,
+        "R" => Operand::Flags,
+        _ => Operand::Value(parse_value(token, symbols)?),
+    };
+    Ok(operand)
+}
+
+/// Encodes an instruction, yielding the column and message of any error.
+fn encode(mnemonic: Token, operands: &[Token], long: bool, symbols: &HashMap<String, u32>) -> Result<Instruction, (usize, String)> {
+    let values = operands
+        .iter()
+        .map(|&operand| parse_operand(operand, symbols).map_err(|message| (operand.column, message)))
+        .collect::<Result<Vec<Operand>, _>>()?;
+    let range = |index: usize, max: u32| -> Result<u32, (usize, String)> {
+        let value = match values[index] {
+            Operand::Value(value) => value,
+            _ => 0,
+        };
+        check_range(value, max).map_err(|message| (operands[index].column, message))
+    };
+    let byte = |index: usize| range(index, 0xFF).map(|value| value as u8);
+    let nibble = |index: usize| range(index, 0xF).map(|value| value as u8);
+    let address = |index: usize| range(index, 0xFFF).map(|value| value as u16);
+
+    use Operand::*;
+    let instruction = match (mnemonic.text.to_ascii_uppercase().as_str(), values.as_slice()) {
+        ("CLS", [])                                        => Instruction::ClearScreen,
+        ("RET", [])                                        => Instruction::Return,
+        ("SCD", [Value(_)])                                => Instruction::ScrollDown { rows: nibble(0)? },
+        ("SCU", [Value(_)])                                => Instruction::ScrollUp { rows: nibble(0)? },
+        ("SCR", [])                                        => Instruction::ScrollRight,
+        ("SCL", [])                                        => Instruction::ScrollLeft,
+        ("EXIT", [])                                       => Instruction::Exit,
+        ("LORES", [])                                      => Instruction::LowResolution,
+        ("HIRES", [])                                      => Instruction::HighResolution,
+        ("SYS", [Value(_)])                                => Instruction::SysCall { address: address(0)? },
+        ("JP", [Value(_)])                                 => Instruction::Jump { address: address(0)? },
+        ("JP", [Register(x), Value(_)])                    => match address(1)? {
+            // With the flexible jump offset quirk (BXNN), the register is given by the first digit of the address.
+            address if *x == 0x0 || address >> 8 == *x as u16 => Instruction::JumpWithOffset { address },
+            _ => return Err((operands[1].column, format!("The address must start with {:X} to jump by V{:X} (BXNN).", x, x))),
+        },
+        ("CALL", [Value(_)])                               => Instruction::Call { address: address(0)? },
+        ("SE", [Register(x), Value(_)])                    => Instruction::SkipIfValue { register: *x, comparand_value: byte(1)? },
+        ("SNE", [Register(x), Value(_)])                   => Instruction::SkipIfNotValue { register: *x, comparand_value: byte(1)? },
+        ("SE", [Register(x), Register(y)])                 => Instruction::SkipIfEqual { register: *x, comparand_register: *y },
+        ("SNE", [Register(x), Register(y)])                => Instruction::SkipIfNotEqual { register: *x, comparand_register: *y },
+        ("LD", [Register(x), Value(_)])                    => Instruction::LoadValue { register: *x, value: byte(1)? },
+        ("ADD", [Register(x), Value(_)])                   => Instruction::AddValue { register: *x, value: byte(1)? },
+        ("LD", [Register(x), Register(y)])                 => Instruction::Load { register: *x, from_register: *y },
+        ("OR", [Register(x), Register(y)])                 => Instruction::Or { register: *x, or_register: *y },
+        ("AND", [Register(x), Register(y)])                => Instruction::And { register: *x, and_register: *y },
+        ("XOR", [Register(x), Register(y)])                => Instruction::Xor { register: *x, xor_register: *y },
+        ("ADD", [Register(x), Register(y)])                => Instruction::Add { register: *x, add_register: *y },
+        ("SUB", [Register(x), Register(y)])                => Instruction::Subtract { register: *x, subtract_register: *y },
+        ("SUBN", [Register(x), Register(y)])               => Instruction::SubtractFrom { register: *x, subtract_from_register: *y },
+        ("SHR", [Register(x), Register(y)])                => Instruction::ShiftRight { register: *x, source_register: *y },
+        ("SHL", [Register(x), Register(y)])                => Instruction::ShiftLeft { register: *x, source_register: *y },
+        ("RND", [Register(x), Value(_)])                   => Instruction::Random { register: *x, mask: byte(1)? },
+        ("DRW", [Register(x), Register(y), Value(_)])      => match nibble(2)? {
+            0 => Instruction::DrawLargeSprite { register_x: *x, register_y: *y },
+            length => Instruction::DrawSprite { register_x: *x, register_y: *y, length },
+        },
+        ("SKP", [Register(x)])                             => Instruction::SkipIfKeyDown { register: *x },
+        ("SKNP", [Register(x)])                            => Instruction::SkipIfKeyUp { register: *x },
+        ("LD", [Register(x), K])                           => Instruction::WaitForKeyDown { register: *x },
+        ("LD", [Register(x), DelayTimer])                  => Instruction::LoadDelayTimer { register: *x },
+        ("LD", [DelayTimer, Register(x)])                  => Instruction::SetDelayTimer { register: *x },
+        ("LD", [SoundTimer, Register(x)])                  => Instruction::SetSoundTimer { register: *x },
+        ("LD", [I, Value(_)]) if long                      => Instruction::LoadLongAddress { address: range(1, 0xFFFF)? as u16 },
+        ("LD", [I, Value(_)])                              => Instruction::LoadAddress { address: address(1)? },
+        ("PLANE", [Value(_)])                              => Instruction::SelectPlanes { planes: nibble(0)? },
+        ("AUDIO", [])                                      => Instruction::LoadAudioPattern,
+        ("PITCH", [Register(x)])                           => Instruction::SetPitch { register: *x },
+        ("ADD", [I, Register(x)])                          => Instruction::AddToAddress { register: *x },
+        ("LD", [Font, Register(x)])                        => Instruction::LoadDigitSpriteAddress { register: *x },
+        ("LD", [LargeFont, Register(x)])                   => Instruction::LoadLargeDigitSpriteAddress { register: *x },
+        ("LD", [Bcd, Register(x)])                         => Instruction::WriteMemoryFromBinaryCodedDecimal { register: *x },
+        ("LD", [IndirectI, Register(x)])                   => Instruction::WriteMemory { end_register: *x },
+        ("LD", [IndirectI, RegisterRange(x, y)])           => Instruction::WriteRegisterRange { register: *x, end_register: *y },
+        ("LD", [RegisterRange(x, y), IndirectI])           => Instruction::ReadRegisterRange { register: *x, end_register: *y },
+        ("LD", [Register(x), IndirectI])                   => Instruction::ReadMemory { end_register: *x },
+        ("LD", [Flags, Register(x)])                       => Instruction::WriteFlags { end_register: *x },
+        ("LD", [Register(x), Flags])                       => Instruction::ReadFlags { end_register: *x },
+        (name, _) if is_mnemonic(name)                     => return Err((mnemonic.column, format!("Invalid operands for {}.", name))),
+        (name, _)                                          => return Err((mnemonic.column, format!("Unknown mnemonic: {}.", name))),
+    };
+    Ok(instruction)
+}
+
+fn is_mnemonic(name: &str) -> bool {
+    [
+        "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LORES", "HIRES", "SYS", "JP", "CALL", "SE", "SNE", "LD",
+        "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+    ].contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::Disassembly;
+    use crate::settings::Settings;
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            SPEED = 0x04        ; constants may be used wherever a value is expected
+            start:
+                LD V0, SPEED
+                LD I, sprite
+            loop: ADD V1, V0
+                DRW V1, V2, 2
+                JP loop
+            sprite:
+                DB 0b11110000, 0x90
+                DW 0x1234
+        ";
+        let program = assemble(source, 0x200).unwrap();
+        assert_eq!(program, vec![0x60, 0x04, 0xA2, 0x0A, 0x81, 0x04, 0xD1, 0x22, 0x12, 0x04, 0xF0, 0x90, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn test_constants_refer_forward() {
+        let source = "
+            LD I, SPRITE
+            SPRITE = BALL
+            DRW V0, V1, SIZE
+            SIZE = 2
+            BALL: DB 0xC0, 0xC0
+        ";
+        assert_eq!(assemble(source, 0x200).unwrap(), vec![0xA2, 0x04, 0xD0, 0x12, 0xC0, 0xC0]);
+    }
+
+    #[test]
+    fn test_assemble_long_instruction() {
+        let program = assemble("LD I, 0x1234\nLD I, data\nEXIT\ndata: DB 1", 0x200).unwrap();
+        assert_eq!(program, vec![0xF0, 0x00, 0x12, 0x34, 0xA2, 0x08, 0x00, 0xFD, 0x01]);
+
+        // The label is beyond 12 bits only once the instructions before it are laid out.
+        let program = assemble("LD I, data\nEXIT\nDW 0\ndata: DB 1", 0xFFA).unwrap();
+        assert_eq!(program, vec![0xF0, 0x00, 0x10, 0x02, 0x00, 0xFD, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| assemble(source, 0x200).unwrap_err();
+        assert_eq!(error("CLS\n  FOO V1"), AssemblyError { line: 2, column: 3, message: "Unknown mnemonic: FOO.".to_string() });
+        assert_eq!(error("LD V1, 0x100").column, 8);
+        assert_eq!(error("JP nowhere").message, "Undefined symbol: nowhere.");
+        assert_eq!(error("SE V1").message, "Invalid operands for SE.");
+        assert_eq!(error("a: CLS\na: CLS").message, "Duplicate symbol: a.");
+        assert_eq!(error("VA = 1").message, "Reserved symbol name: VA.");
+        assert_eq!(error("JP V2, 0x123").message, "The address must start with 2 to jump by V2 (BXNN).");
+        assert_eq!(error("X = Y\nY = X").message, "Circular definition of constant: X.");
+        assert_eq!(error("X = 1\nX: CLS").message, "Duplicate symbol: X.");
+    }
+
+    #[test]
+    fn test_round_trips_disassembly() {
+        let source = "
+                CLS
+                LD I, sprite
+                HIRES
+                LD [I], V1 - V3
+                JP V0, end
+                CALL draw
+                SE V0, 0x01
+                DW 0x5121
+            end:
+                JP end
+            draw:
+                DRW V0, V1, 0
+                LD V2, [I]
+                SUBN VA, VB
+                SKNP V3
+                RET
+            sprite:
+                DB 0xFF, 0x81, 0xFF
+        ";
+        let program = assemble(source, 0x200).unwrap();
+        let listing = Disassembly::new(&program, &Settings::default()).listing();
+        assert_eq!(assemble(&listing, 0x200).unwrap(), program);
+    }
+}
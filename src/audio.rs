@@ -0,0 +1,47 @@
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// An XO-CHIP 1-bit audio pattern of 128 samples, played back at a rate determined by the pitch register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioPattern {
+    pub buffer: [u8; AUDIO_PATTERN_SIZE],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    pub fn new(buffer: [u8; AUDIO_PATTERN_SIZE], pitch: u8) -> Self {
+        Self { buffer, pitch }
+    }
+
+    pub fn sample_count(&self) -> usize {
+        AUDIO_PATTERN_SIZE * 8
+    }
+
+    /// Playback rate in samples per second.
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+    }
+
+    pub fn sample(&self, index: usize) -> bool {
+        let index = index % self.sample_count();
+        self.buffer[index / 8] & (0x80 >> (index % 8)) > 0
+    }
+}
+
+/// Settings for the buzzer played while the sound timer is active.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpeakerSettings {
+    /// Frequency of the default square wave beep in Hz.
+    pub frequency: u16,
+    /// Volume from 0 (muted) to 255.
+    pub volume: u8,
+}
+
+impl Default for SpeakerSettings {
+    fn default() -> Self {
+        Self {
+            frequency: 220,
+            volume: 75,
+        }
+    }
+}
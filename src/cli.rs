@@ -0,0 +1,526 @@
+use crate::config::{Colors, ColorsProfile, Persistence, Profile, Rgb};
+use crate::terminal_frontend::Charset;
+use chip8::settings::{Platform, Settings};
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: chip8 [OPTIONS] <ROM>
+       chip8 disasm [--platform <NAME>] [--program-start-address <ADDRESS>] <ROM>
+       chip8 asm [--program-start-address <ADDRESS>] [-o <OUTPUT>] <SOURCE>
+
+Subcommands:
+    disasm                                Print an assembly listing of the ROM, separating code from data
+    asm                                   Assemble the source into a ROM (default output: <SOURCE>.ch8)
+
+Configuration:
+    --config <PATH>                       Read the config file at the given path
+                                          (default: $XDG_CONFIG_HOME/chip8/config.toml)
+    --print-config                        Print the effective configuration and exit
+    --no-database                         Do not detect the ROM using the bundled program database
+
+Platform:
+    --platform <NAME>                     Use the preset of cosmac-vip, chip-48, super-chip or xo-chip
+
+Emulation:
+    --clock-speed <HZ>                    Clock speed in Hz
+    --frame-rate <HZ>                     Frame rate in Hz
+    --memory-size <BYTES>                 Memory size in bytes (up to 65536)
+    --stack-depth <LEVELS>                Maximum number of nested subroutine calls
+    --display-width <PX>                  Display width in low resolution mode
+    --display-height <PX>                 Display height in low resolution mode
+    --sprite-start-address <ADDRESS>      Memory address of the font sprites
+    --large-sprite-start-address <ADDRESS>
+                                          Memory address of the large (SUPER-CHIP) font sprites
+    --program-start-address <ADDRESS>     Memory address at which the program is loaded
+    --seed <SEED>                         Seed of the random number generator (default: random)
+
+Quirks (each can be disabled with --no-<QUIRK>):
+    --in-place-shift                      Shift VX in-place rather than VY (8XY6, 8XYE)
+    --flexible-jump-offset                Jump with offset from VX rather than V0 (BNNN)
+    --auto-address-increments             Increment I on memory reads and writes (FX55, FX65)
+    --flag-reset-on-logic-ops             Reset VF on OR, AND and XOR (8XY1, 8XY2, 8XY3)
+    --sprite-wrapping                     Wrap sprites around the display edges instead of clipping
+    --sprite-draw-delay                   Wait for the next frame after drawing a sprite
+    --large-sprites                       Draw 16x16 sprites rather than nothing (DXY0)
+    --legacy-low-resolution               Draw 8x16 sprites (DXY0) and scroll half as far in low resolution, like SUPER-CHIP 1.1
+
+Frontend:
+    --frequency <HZ>                      Buzzer frequency in Hz
+    --volume <0-255>                      Buzzer volume
+    --scale <FACTOR>                      Initial window scale
+    --rewind-seconds <SECONDS>            Seconds of gameplay that can be rewound (0 disables rewinding)
+    --screenshot-scale <FACTOR>           Scale of screenshots and recordings (at most 16)
+    --palette <NAME>                      Use the colors of classic, green, amber, lcd or octo (cycle with F9)
+    --colors <COLORS>                     Comma separated colors of the background, the foreground and, for XO-CHIP,
+                                          pixels lit only in the second plane and in both planes (e.g. #000000,#33FF66)
+    --persistence <MODE>                  Let erased pixels linger to reduce flicker: off, blend (with the previous frame)
+                                          or decay (fading out like CRT phosphor)
+    --decay-frames <N>                    Frames over which erased pixels fade out with --persistence decay (default: 4)
+    --record-gif <PATH>                   Record every frame into an animated GIF (toggle with F10)
+    --record-y4m <PATH>                   Stream every frame as raw YUV4MPEG2 video
+    --record-movie <PATH>                 Record the input of every frame into a movie, for exact playback
+    --play-movie <PATH>                   Play back the input of a movie instead of the keyboard or input script
+    --debug                               Start paused, with an interactive debugger on standard input
+    --terminal                            Draw the display in the terminal with half block characters, instead of a window
+    --braille                             Draw the display in the terminal with braille characters, for small terminals
+
+Headless:
+    --headless                            Run without a window or audio device
+    --frames <N>                          Stop after N frames (default: run until the program halts)
+    --input <PATH>                        Hold keys as given by an input script: lines of
+                                          <FRAME> <KEYS>, with keys in hex (e.g. 120 4 6) or - for none
+    --dump-display <PATH>                 Write the final display as text (# for lit pixels, - for stdout)
+    --dump-registers <PATH>               Write the final registers as text (- for stdout)
+    --dump-memory <PATH>                  Write the final memory as raw bytes (- for a hexdump on stdout)
+    --screenshot-at-frame <N> <PATH>      Write a PNG screenshot after N frames (may be repeated)
+
+    -h, --help                            Print this help
+
+Numeric values may be given in decimal or, prefixed with 0x, in hexadecimal.
+Command-line options take precedence over ROM specific sections of the config file, then over settings
+detected from the program database, and finally over the config file defaults.
+In headless mode, the exit status is 0 if the program exited, 3 if the frame limit was reached and 4 if
+the program was halted by a fault.
+";
+
+pub struct Options {
+    pub program_path: Option<String>,
+    pub config_path: Option<PathBuf>,
+    pub print_config: bool,
+    pub use_database: bool,
+    pub debug: bool,
+    pub record_gif: Option<PathBuf>,
+    pub record_y4m: Option<PathBuf>,
+    pub record_movie: Option<PathBuf>,
+    pub play_movie: Option<PathBuf>,
+    /// The characters to draw the display with in the terminal, if the terminal frontend was chosen.
+    pub terminal: Option<Charset>,
+    /// Options for running without a frontend, if `--headless` was given.
+    pub headless: Option<HeadlessOptions>,
+    pub profile: Profile,
+}
+
+#[derive(Default)]
+pub struct HeadlessOptions {
+    pub frames: Option<u32>,
+    pub input_path: Option<PathBuf>,
+    pub display_path: Option<PathBuf>,
+    pub registers_path: Option<PathBuf>,
+    pub memory_path: Option<PathBuf>,
+    /// Screenshots to take, by the number of frames after which to take them.
+    pub screenshots: Vec<(u32, PathBuf)>,
+}
+
+pub enum Command {
+    Run(Box<Options>),
+    /// Disassembles with the settings of the platform, which determine the start address and how quirky instructions
+    /// are shown.
+    Disassemble { program_path: String, settings: Settings },
+    Assemble { source_path: PathBuf, output_path: PathBuf, start_address: u16 },
+    Help,
+}
+
+pub fn parse<I>(args: I) -> Result<Command, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let args: Vec<String> = args.into_iter().collect();
+    match args.first().map(String::as_str) {
+        Some("disasm") => return parse_tool(&args[1..], false),
+        Some("asm") => return parse_tool(&args[1..], true),
+        _ => {}
+    }
+
+    let mut profile = Profile::default();
+    let mut program_path = None;
+    let mut config_path = None;
+    let mut print_config = false;
+    let mut use_database = true;
+    let mut debug = false;
+    let mut record_gif = None;
+    let mut record_y4m = None;
+    let mut record_movie = None;
+    let mut play_movie = None;
+    let mut terminal = None;
+    let mut headless = false;
+    let mut headless_options = HeadlessOptions::default();
+    let mut headless_option = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        let value_index = i + 1;
+        let mut takes_value = true;
+        match arg {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--config" => config_path = Some(PathBuf::from(parse_string(&args, value_index)?)),
+            "--print-config" => {
+                takes_value = false;
+                print_config = true;
+            },
+            "--no-database" => {
+                takes_value = false;
+                use_database = false;
+            },
+            "--debug" => {
+                takes_value = false;
+                debug = true;
+            },
+            "--record-gif" => record_gif = Some(PathBuf::from(parse_string(&args, value_index)?)),
+            "--record-y4m" => record_y4m = Some(PathBuf::from(parse_string(&args, value_index)?)),
+            "--record-movie" => record_movie = Some(PathBuf::from(parse_string(&args, value_index)?)),
+            "--play-movie" => play_movie = Some(PathBuf::from(parse_string(&args, value_index)?)),
+            "--terminal" => {
+                takes_value = false;
+                terminal = terminal.or(Some(Charset::HalfBlocks));
+            },
+            "--braille" => {
+                takes_value = false;
+                terminal = Some(Charset::Braille);
+            },
+            "--headless" => {
+                takes_value = false;
+                headless = true;
+            },
+            "--frames" => {
+                headless_options.frames = Some(parse_number(&args, value_index)?);
+                headless_option = Some(arg);
+            },
+            "--input" | "--dump-display" | "--dump-registers" | "--dump-memory" => {
+                let path = Some(PathBuf::from(parse_string(&args, value_index)?));
+                match arg {
+                    "--input" => headless_options.input_path = path,
+                    "--dump-display" => headless_options.display_path = path,
+                    "--dump-registers" => headless_options.registers_path = path,
+                    _ => headless_options.memory_path = path,
+                }
+                headless_option = Some(arg);
+            },
+            "--screenshot-at-frame" => {
+                let frame = parse_number(&args, value_index)?;
+                let path = args.get(value_index + 1).map(PathBuf::from).ok_or_else(|| format!("Missing value for {}.", arg))?;
+                headless_options.screenshots.push((frame, path));
+                headless_option = Some(arg);
+                // Skip the second value here, as only one is accounted for below.
+                i += 1;
+            },
+            "--platform" => {
+                let platform = parse_string(&args, value_index)?;
+                platform.parse::<Platform>()?;
+                profile.platform = Some(platform);
+            },
+            "--clock-speed" => profile.clock_speed = Some(parse_number(&args, value_index)?),
+            "--frame-rate" => profile.frame_rate = Some(parse_number(&args, value_index)?),
+            "--memory-size" => profile.memory_size = Some(parse_number(&args, value_index)?),
+            "--stack-depth" => profile.stack_depth = Some(parse_number(&args, value_index)?),
+            "--seed" => profile.seed = Some(parse_number(&args, value_index)?),
+            "--display-width" => profile.display_width = Some(parse_number(&args, value_index)?),
+            "--display-height" => profile.display_height = Some(parse_number(&args, value_index)?),
+            "--sprite-start-address" => profile.sprite_start_address = Some(parse_number(&args, value_index)?),
+            "--large-sprite-start-address" => profile.large_sprite_start_address = Some(parse_number(&args, value_index)?),
+            "--program-start-address" => profile.program_start_address = Some(parse_number(&args, value_index)?),
+            "--frequency" => profile.frequency = Some(parse_number(&args, value_index)?),
+            "--volume" => profile.volume = Some(parse_number(&args, value_index)?),
+            "--scale" => profile.scale = Some(parse_number(&args, value_index)?),
+            "--rewind-seconds" => profile.rewind_seconds = Some(parse_number(&args, value_index)?),
+            "--screenshot-scale" => profile.screenshot_scale = Some(parse_number(&args, value_index)?),
+            "--palette" => {
+                let palette = parse_string(&args, value_index)?;
+                Colors::named(&palette)?;
+                profile.palette = Some(palette);
+            },
+            "--colors" => profile.colors = Some(parse_colors(&args, value_index)?),
+            "--persistence" => {
+                let persistence = parse_string(&args, value_index)?;
+                persistence.parse::<Persistence>()?;
+                profile.persistence = Some(persistence);
+            },
+            "--decay-frames" => profile.decay_frames = Some(parse_number(&args, value_index)?),
+            _ if arg.starts_with("--") => {
+                takes_value = false;
+                let (quirk, enabled) = match arg.strip_prefix("--no-") {
+                    Some(quirk) => (quirk, false),
+                    None => (&arg[2..], true),
+                };
+                let enabled = Some(enabled);
+                match quirk {
+                    "in-place-shift" => profile.in_place_shift = enabled,
+                    "flexible-jump-offset" => profile.flexible_jump_offset = enabled,
+                    "auto-address-increments" => profile.auto_address_increments = enabled,
+                    "flag-reset-on-logic-ops" => profile.flag_reset_on_logic_ops = enabled,
+                    "sprite-wrapping" => profile.sprite_wrapping = enabled,
+                    "sprite-draw-delay" => profile.sprite_draw_delay = enabled,
+                    "large-sprites" => profile.large_sprites = enabled,
+                    "legacy-low-resolution" => profile.legacy_low_resolution = enabled,
+                    _ => return Err(format!("Unknown option: {}.", arg)),
+                }
+            },
+            _ => {
+                takes_value = false;
+                if program_path.replace(arg.to_string()).is_some() {
+                    return Err(format!("Unexpected argument: {}.", arg));
+                }
+            }
+        }
+        i += if takes_value { 2 } else { 1 };
+    }
+
+    if program_path.is_none() && !print_config {
+        return Err("Please provide a path to a valid program.".to_string());
+    }
+
+    if let Some(option) = headless_option.filter(|_| !headless) {
+        return Err(format!("{} requires --headless.", option));
+    }
+    if headless && debug {
+        return Err("--debug cannot be combined with --headless.".to_string());
+    }
+    if terminal.is_some() && headless {
+        return Err("--terminal cannot be combined with --headless.".to_string());
+    }
+    // The debugger reads commands from the terminal, which the terminal frontend reads keys from.
+    if terminal.is_some() && debug {
+        return Err("--debug cannot be combined with --terminal.".to_string());
+    }
+    if record_movie.is_some() && play_movie.is_some() {
+        return Err("--record-movie cannot be combined with --play-movie.".to_string());
+    }
+    // The debugger runs partial frames, which movies cannot reproduce.
+    if debug && (record_movie.is_some() || play_movie.is_some()) {
+        return Err("--debug cannot be combined with movies.".to_string());
+    }
+    if play_movie.is_some() && headless_options.input_path.is_some() {
+        return Err("--input cannot be combined with --play-movie.".to_string());
+    }
+    let headless = headless.then_some(headless_options);
+
+    Ok(Command::Run(Box::new(Options {
+        program_path,
+        config_path,
+        print_config,
+        use_database,
+        debug,
+        record_gif,
+        record_y4m,
+        record_movie,
+        play_movie,
+        terminal,
+        headless,
+        profile,
+    })))
+}
+
+/// Parses the arguments of the disasm and asm subcommands.
+fn parse_tool(args: &[String], assemble: bool) -> Result<Command, String> {
+    let mut input_path = None;
+    let mut output_path = None;
+    let mut platform = None;
+    let mut start_address = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        match arg {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--program-start-address" => {
+                start_address = Some(parse_number(args, i + 1)?);
+                i += 1;
+            },
+            "--platform" if !assemble => {
+                platform = Some(parse_string(args, i + 1)?.parse::<Platform>()?);
+                i += 1;
+            },
+            "-o" | "--output" if assemble => {
+                output_path = Some(PathBuf::from(parse_string(args, i + 1)?));
+                i += 1;
+            },
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}.", arg)),
+            _ => {
+                if input_path.replace(arg.to_string()).is_some() {
+                    return Err(format!("Unexpected argument: {}.", arg));
+                }
+            }
+        }
+        i += 1;
+    }
+
+    if !assemble {
+        let program_path = input_path.ok_or("Please provide a path to a valid program.")?;
+        let mut settings = platform.map(Settings::preset).unwrap_or_default();
+        settings.program_start_address = start_address.unwrap_or(settings.program_start_address);
+        return Ok(Command::Disassemble { program_path, settings });
+    }
+
+    let source_path = PathBuf::from(input_path.ok_or("Please provide a path to the assembly source.")?);
+    let output_path = output_path.unwrap_or_else(|| source_path.with_extension("ch8"));
+    let start_address = start_address.unwrap_or(Settings::default().program_start_address);
+    Ok(Command::Assemble { source_path, output_path, start_address })
+}
+
+fn parse_string(args: &[String], index: usize) -> Result<String, String> {
+    args.get(index).cloned().ok_or_else(|| format!("Missing value for {}.", args[index - 1]))
+}
+
+/// Parses between two and four comma separated colors, in the order of pixel values.
+fn parse_colors(args: &[String], index: usize) -> Result<ColorsProfile, String> {
+    let value = parse_string(args, index)?;
+    let colors: Vec<String> = value.split(',').map(|color| color.trim().to_string()).collect();
+    if !(2..=4).contains(&colors.len()) {
+        return Err(format!("Invalid value for {}: {}.", args[index - 1], value));
+    }
+    for color in &colors {
+        Rgb::parse(color)?;
+    }
+    let color = |index: usize| colors.get(index).cloned();
+    Ok(ColorsProfile { background: color(0), foreground: color(1), foreground2: color(2), blend: color(3) })
+}
+
+fn parse_number<T>(args: &[String], index: usize) -> Result<T, String>
+where
+    T: TryFrom<u64>,
+{
+    let option = &args[index - 1];
+    let value = args.get(index).ok_or_else(|| format!("Missing value for {}.", option))?;
+    let number = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse::<u64>(),
+    };
+    number
+        .ok()
+        .and_then(|number| T::try_from(number).ok())
+        .ok_or_else(|| format!("Invalid value for {}: {}.", option, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_options(args: &[&str]) -> Result<Options, String> {
+        match parse(args.iter().map(|arg| arg.to_string()))? {
+            Command::Run(options) => Ok(*options),
+            _ => Err("Unexpected command.".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let options = parse_options(&["rom.ch8"]).unwrap();
+        assert_eq!(options.program_path.as_deref(), Some("rom.ch8"));
+        assert_eq!(options.profile.clock_speed, None);
+        assert!(!options.print_config);
+        assert!(!options.debug);
+        assert!(options.headless.is_none());
+        assert!(options.record_gif.is_none());
+        assert!(options.terminal.is_none());
+    }
+
+    #[test]
+    fn test_terminal_options() {
+        assert_eq!(parse_options(&["--terminal", "rom.ch8"]).unwrap().terminal, Some(Charset::HalfBlocks));
+        assert_eq!(parse_options(&["--braille", "--terminal", "rom.ch8"]).unwrap().terminal, Some(Charset::Braille));
+        assert!(parse_options(&["--terminal", "--headless", "rom.ch8"]).is_err());
+        assert!(parse_options(&["--terminal", "--debug", "rom.ch8"]).is_err());
+    }
+
+    #[test]
+    fn test_recording_options() {
+        let options = parse_options(&["--record-gif", "out.gif", "--headless", "--record-y4m", "out.y4m", "rom.ch8"]).unwrap();
+        assert_eq!(options.record_gif, Some(PathBuf::from("out.gif")));
+        assert_eq!(options.record_y4m, Some(PathBuf::from("out.y4m")));
+        assert!(options.headless.is_some());
+    }
+
+    #[test]
+    fn test_movie_options() {
+        let options = parse_options(&["--play-movie", "run.movie", "rom.ch8"]).unwrap();
+        assert_eq!(options.play_movie, Some(PathBuf::from("run.movie")));
+        assert!(options.record_movie.is_none());
+
+        assert!(parse_options(&["--play-movie", "a.movie", "--record-movie", "b.movie", "rom.ch8"]).is_err());
+        assert!(parse_options(&["--record-movie", "run.movie", "--debug", "rom.ch8"]).is_err());
+        assert!(parse_options(&["--headless", "--input", "keys.txt", "--play-movie", "run.movie", "rom.ch8"]).is_err());
+    }
+
+    #[test]
+    fn test_headless_options() {
+        let options = parse_options(&["--headless", "--frames", "600", "--input", "keys.txt", "--dump-display", "-", "rom.ch8"]).unwrap();
+        let headless = options.headless.unwrap();
+        assert_eq!(headless.frames, Some(600));
+        assert_eq!(headless.input_path, Some(PathBuf::from("keys.txt")));
+        assert_eq!(headless.display_path, Some(PathBuf::from("-")));
+        assert_eq!(headless.memory_path, None);
+        assert!(headless.screenshots.is_empty());
+
+        let options = parse_options(&["--headless", "--screenshot-at-frame", "60", "shot.png", "rom.ch8"]).unwrap();
+        assert_eq!(options.headless.unwrap().screenshots, vec![(60, PathBuf::from("shot.png"))]);
+        assert!(parse_options(&["--headless", "--screenshot-at-frame", "60"]).is_err());
+
+        assert!(parse_options(&["--frames", "600", "rom.ch8"]).is_err());
+        assert!(parse_options(&["--headless", "--debug", "rom.ch8"]).is_err());
+    }
+
+    #[test]
+    fn test_options() {
+        let options = parse_options(&["--clock-speed", "0x100", "--no-in-place-shift", "--platform", "super-chip", "--rewind-seconds", "0", "--seed", "42", "--large-sprites", "rom.ch8"]).unwrap();
+        assert_eq!(options.profile.clock_speed, Some(0x100));
+        assert_eq!(options.profile.in_place_shift, Some(false));
+        assert_eq!(options.profile.platform.as_deref(), Some("super-chip"));
+        assert_eq!(options.profile.rewind_seconds, Some(0));
+        assert_eq!(options.profile.seed, Some(42));
+        assert_eq!(options.profile.large_sprites, Some(true));
+
+        let options = parse_options(&["--palette", "amber", "--colors", "#000000, #33FF66", "rom.ch8"]).unwrap();
+        assert_eq!(options.profile.palette.as_deref(), Some("amber"));
+        let colors = options.profile.colors.unwrap();
+        assert_eq!(colors.foreground.as_deref(), Some("#33FF66"));
+        assert_eq!(colors.blend, None);
+
+        let options = parse_options(&["--persistence", "decay", "--decay-frames", "6", "rom.ch8"]).unwrap();
+        assert_eq!(options.profile.persistence.as_deref(), Some("decay"));
+        assert_eq!(options.profile.decay_frames, Some(6));
+    }
+
+    #[test]
+    fn test_print_config_without_rom() {
+        let options = parse_options(&["--print-config", "--config", "chip8.toml"]).unwrap();
+        assert!(options.print_config);
+        assert_eq!(options.program_path, None);
+        assert_eq!(options.config_path, Some(PathBuf::from("chip8.toml")));
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        assert!(parse_options(&["--volume", "256", "rom.ch8"]).is_err());
+        assert!(parse_options(&["--platform", "chip-9", "rom.ch8"]).is_err());
+        assert!(parse_options(&["--persistence", "glow", "rom.ch8"]).is_err());
+        assert!(parse_options(&["--palette", "sepia", "rom.ch8"]).is_err());
+        assert!(parse_options(&["--colors", "#000000", "rom.ch8"]).is_err());
+        assert!(parse_options(&["--colors", "#000000,#FFFFFF,#FF0000,#00FF00,#0000FF", "rom.ch8"]).is_err());
+        assert!(parse_options(&["--unknown", "rom.ch8"]).is_err());
+        assert!(parse_options(&["--scale"]).is_err());
+        assert!(parse_options(&[]).is_err());
+    }
+
+    #[test]
+    fn test_help() {
+        assert!(matches!(parse(vec!["rom.ch8".to_string(), "--help".to_string()]), Ok(Command::Help)));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let command = parse(["disasm", "--program-start-address", "0x600", "rom.ch8"].map(str::to_string)).unwrap();
+        assert!(matches!(command, Command::Disassemble { ref program_path, settings } if program_path == "rom.ch8" && settings.program_start_address == 0x600));
+        let command = parse(["disasm", "--platform", "super-chip", "rom.ch8"].map(str::to_string)).unwrap();
+        assert!(matches!(command, Command::Disassemble { settings, .. } if settings.use_flexible_jump_offset));
+        assert!(parse(["disasm"].map(str::to_string)).is_err());
+    }
+
+    #[test]
+    fn test_assemble() {
+        let command = parse(["asm", "demo.s"].map(str::to_string)).unwrap();
+        assert!(matches!(command, Command::Assemble { ref output_path, start_address: 0x200, .. } if output_path == &PathBuf::from("demo.ch8")));
+        let command = parse(["asm", "demo.s", "-o", "out.ch8"].map(str::to_string)).unwrap();
+        assert!(matches!(command, Command::Assemble { ref output_path, .. } if output_path == &PathBuf::from("out.ch8")));
+        assert!(parse(["disasm", "-o", "out.ch8", "rom.ch8"].map(str::to_string)).is_err());
+    }
+}
@@ -0,0 +1,576 @@
+use chip8::audio::SpeakerSettings;
+use chip8::settings::{Platform, Settings};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const KEY_COUNT: usize = 16;
+const DEFAULT_WINDOW_SCALE: u32 = 10;
+const DEFAULT_REWIND_SECONDS: u32 = 10;
+const DEFAULT_SCREENSHOT_SCALE: u32 = 4;
+/// Keeps screenshots of the largest display within a few dozen megabytes, and GIF dimensions within 16 bits.
+const MAX_SCREENSHOT_SCALE: u32 = 16;
+const DEFAULT_DECAY_FRAMES: u8 = 4;
+/// The sizes in bytes of the font sprites and the large (SUPER-CHIP) font sprites.
+const FONT_SIZE: u32 = 16 * 5;
+const LARGE_FONT_SIZE: u32 = 16 * 10;
+const DEFAULT_REWIND_KEY: &str = "Backspace";
+const DEFAULT_KEY_BINDINGS: [&str; KEY_COUNT] = [
+    "X", "1", "2", "3",
+    "Q", "W", "E", "A",
+    "S", "D", "Z", "C",
+    "4", "R", "F", "V",
+];
+
+/// A set of optional overrides, as specified by the config file, a ROM specific section of it, or the command line.
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub platform: Option<String>,
+    pub clock_speed: Option<u16>,
+    pub frame_rate: Option<u16>,
+    pub memory_size: Option<u32>,
+    pub stack_depth: Option<u16>,
+    pub seed: Option<u32>,
+    pub display_width: Option<u8>,
+    pub display_height: Option<u8>,
+    pub sprite_start_address: Option<u16>,
+    pub large_sprite_start_address: Option<u16>,
+    pub program_start_address: Option<u16>,
+    pub in_place_shift: Option<bool>,
+    pub flexible_jump_offset: Option<bool>,
+    pub auto_address_increments: Option<bool>,
+    pub flag_reset_on_logic_ops: Option<bool>,
+    pub sprite_wrapping: Option<bool>,
+    pub sprite_draw_delay: Option<bool>,
+    pub large_sprites: Option<bool>,
+    pub legacy_low_resolution: Option<bool>,
+    pub frequency: Option<u16>,
+    pub volume: Option<u8>,
+    pub scale: Option<u32>,
+    pub rewind_seconds: Option<u32>,
+    /// The name of the keyboard key held to rewind.
+    pub rewind_key: Option<String>,
+    pub screenshot_scale: Option<u32>,
+    pub persistence: Option<String>,
+    pub decay_frames: Option<u8>,
+    /// The name of a built-in palette, which the colors are then applied on top of.
+    pub palette: Option<String>,
+    /// Keyboard key names by CHIP-8 key (0 - F).
+    pub keys: Option<BTreeMap<String, String>>,
+    pub colors: Option<ColorsProfile>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColorsProfile {
+    pub background: Option<String>,
+    pub foreground: Option<String>,
+    /// The color of pixels lit only in the second plane (XO-CHIP).
+    pub foreground2: Option<String>,
+    /// The color of pixels lit in both planes (XO-CHIP).
+    pub blend: Option<String>,
+}
+
+/// The config file, with global defaults and overrides for ROMs keyed by file name or SHA-1 hash.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub defaults: Profile,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub roms: BTreeMap<String, Profile>,
+}
+
+impl ConfigFile {
+    /// The config file location, following the XDG base directory specification.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_home.join("chip8").join("config.toml"))
+    }
+
+    /// Loads the config file at the given path. A missing file yields an empty config, unless it was explicitly requested.
+    pub fn load(path: &PathBuf, required: bool) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|error| format!("Invalid config file {}: {}", path.display(), error)),
+            Err(_) if !required => Ok(Self::default()),
+            Err(error) => Err(format!("Could not read config file {}: {}.", path.display(), error)),
+        }
+    }
+
+    /// The ROM specific profiles applying to the given ROM, in order of increasing precedence.
+    pub fn rom_profiles(&self, rom_name: &str, rom_sha1: &str) -> Vec<&Profile> {
+        self.roms.get(rom_name).into_iter().chain(self.roms.get(rom_sha1)).collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let hex = value.strip_prefix('#').unwrap_or(value);
+        let rgb = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6);
+        match rgb {
+            Some(rgb) => Ok(Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)),
+            None => Err(format!("Invalid color: {}.", value)),
+        }
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.0, self.1, self.2)
+    }
+}
+
+/// How the frontends let pixels linger after they are erased, hiding the flicker of sprites being redrawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Persistence {
+    /// Pixels are shown exactly as they are in the display.
+    #[default]
+    Off,
+    /// Pixels lit in either the current or the previous frame are shown lit.
+    Blend,
+    /// Erased pixels fade out over a number of frames, like the phosphor of CRT screens.
+    Decay,
+}
+
+impl Persistence {
+    pub const ALL: [Persistence; 3] = [Persistence::Off, Persistence::Blend, Persistence::Decay];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Persistence::Off => "off",
+            Persistence::Blend => "blend",
+            Persistence::Decay => "decay",
+        }
+    }
+}
+
+impl fmt::Display for Persistence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Persistence {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Persistence::ALL
+            .into_iter()
+            .find(|persistence| persistence.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown persistence mode: {}.", name))
+    }
+}
+
+/// The colors of pixels, by the planes they are lit in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Colors {
+    pub background: Rgb,
+    /// The color of pixels lit in the first plane, which is the only plane before XO-CHIP.
+    pub foreground: Rgb,
+    /// The color of pixels lit only in the second plane.
+    pub foreground2: Rgb,
+    /// The color of pixels lit in both planes.
+    pub blend: Rgb,
+}
+
+/// The built-in palettes, by name. The first one is the default.
+pub const PALETTES: [(&str, Colors); 5] = [
+    ("classic", Colors {
+        background: Rgb(0x00, 0x00, 0x00),
+        foreground: Rgb(0xFF, 0xFF, 0xFF),
+        foreground2: Rgb(0xAA, 0xAA, 0xAA),
+        blend: Rgb(0x55, 0x55, 0x55),
+    }),
+    ("green", Colors {
+        background: Rgb(0x0A, 0x14, 0x0A),
+        foreground: Rgb(0x33, 0xFF, 0x66),
+        foreground2: Rgb(0x1A, 0x80, 0x33),
+        blend: Rgb(0xAA, 0xFF, 0xBB),
+    }),
+    ("amber", Colors {
+        background: Rgb(0x1A, 0x0F, 0x00),
+        foreground: Rgb(0xFF, 0xB0, 0x00),
+        foreground2: Rgb(0x80, 0x58, 0x00),
+        blend: Rgb(0xFF, 0xDD, 0x88),
+    }),
+    ("lcd", Colors {
+        background: Rgb(0x9B, 0xBC, 0x0F),
+        foreground: Rgb(0x0F, 0x38, 0x0F),
+        foreground2: Rgb(0x8B, 0xAC, 0x0F),
+        blend: Rgb(0x30, 0x62, 0x30),
+    }),
+    ("octo", Colors {
+        background: Rgb(0x99, 0x66, 0x00),
+        foreground: Rgb(0xFF, 0xCC, 0x00),
+        foreground2: Rgb(0xFF, 0x66, 0x00),
+        blend: Rgb(0x66, 0x22, 0x00),
+    }),
+];
+
+impl Colors {
+    /// The built-in palette with the given name.
+    pub fn named(name: &str) -> Result<Self, String> {
+        PALETTES
+            .iter()
+            .find(|(palette_name, _)| palette_name.eq_ignore_ascii_case(name))
+            .map(|&(_, colors)| colors)
+            .ok_or_else(|| format!("Unknown palette: {}.", name))
+    }
+
+    /// The color of a pixel, given the planes it is lit in, as returned by `Display::pixel`.
+    pub fn pixel(&self, pixel: u8) -> Rgb {
+        self.palette()[pixel as usize & 0b11]
+    }
+
+    /// The colors indexed by pixel value.
+    pub fn palette(&self) -> [Rgb; 4] {
+        [self.background, self.foreground, self.foreground2, self.blend]
+    }
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        PALETTES[0].1
+    }
+}
+
+/// The effective configuration, after merging defaults, the config file and command line options.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub platform: Option<Platform>,
+    pub settings: Settings,
+    pub speaker_settings: SpeakerSettings,
+    pub window_scale: u32,
+    /// The number of seconds of gameplay that can be rewound, or 0 to disable rewinding.
+    pub rewind_seconds: u32,
+    /// The name of the keyboard key held to rewind.
+    pub rewind_key: String,
+    /// The factor by which screenshots are scaled up from the display resolution.
+    pub screenshot_scale: u32,
+    pub persistence: Persistence,
+    /// The number of frames over which erased pixels fade out, when they decay.
+    pub decay_frames: u8,
+    /// Keyboard key names by CHIP-8 key.
+    pub keys: [String; KEY_COUNT],
+    pub colors: Colors,
+}
+
+impl Config {
+    /// Merges the given profiles on top of the built-in defaults, in order of increasing precedence.
+    pub fn resolve(profiles: &[&Profile]) -> Result<Self, String> {
+        // The last platform specified determines the base settings, which all other overrides are applied on top of.
+        let platform = profiles
+            .iter()
+            .rev()
+            .find_map(|profile| profile.platform.as_deref())
+            .map(str::parse::<Platform>)
+            .transpose()?;
+
+        let mut config = Self {
+            platform,
+            settings: platform.map(Settings::preset).unwrap_or_default(),
+            speaker_settings: SpeakerSettings::default(),
+            window_scale: DEFAULT_WINDOW_SCALE,
+            rewind_seconds: DEFAULT_REWIND_SECONDS,
+            rewind_key: DEFAULT_REWIND_KEY.to_string(),
+            screenshot_scale: DEFAULT_SCREENSHOT_SCALE,
+            persistence: Persistence::default(),
+            decay_frames: DEFAULT_DECAY_FRAMES,
+            keys: DEFAULT_KEY_BINDINGS.map(str::to_string),
+            colors: Colors::default(),
+        };
+
+        for profile in profiles {
+            config.apply(profile)?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply(&mut self, profile: &Profile) -> Result<(), String> {
+        let settings = &mut self.settings;
+        set(&mut settings.clock_speed, profile.clock_speed);
+        set(&mut settings.frame_rate, profile.frame_rate);
+        set(&mut settings.memory_size, profile.memory_size);
+        set(&mut settings.stack_depth, profile.stack_depth);
+        if profile.seed.is_some() {
+            settings.random_seed = profile.seed;
+        }
+        set(&mut settings.display_width, profile.display_width);
+        set(&mut settings.display_height, profile.display_height);
+        set(&mut settings.sprite_start_address, profile.sprite_start_address);
+        set(&mut settings.large_sprite_start_address, profile.large_sprite_start_address);
+        set(&mut settings.program_start_address, profile.program_start_address);
+        set(&mut settings.use_in_place_shift, profile.in_place_shift);
+        set(&mut settings.use_flexible_jump_offset, profile.flexible_jump_offset);
+        set(&mut settings.use_auto_address_increments, profile.auto_address_increments);
+        set(&mut settings.use_flag_reset_on_logic_ops, profile.flag_reset_on_logic_ops);
+        set(&mut settings.use_sprite_wrapping, profile.sprite_wrapping);
+        set(&mut settings.use_sprite_draw_delay, profile.sprite_draw_delay);
+        set(&mut settings.use_large_sprites, profile.large_sprites);
+        set(&mut settings.use_legacy_low_resolution, profile.legacy_low_resolution);
+        set(&mut self.speaker_settings.frequency, profile.frequency);
+        set(&mut self.speaker_settings.volume, profile.volume);
+        set(&mut self.window_scale, profile.scale);
+        set(&mut self.rewind_seconds, profile.rewind_seconds);
+        set(&mut self.rewind_key, profile.rewind_key.clone());
+        set(&mut self.screenshot_scale, profile.screenshot_scale);
+        set(&mut self.persistence, profile.persistence.as_deref().map(str::parse).transpose()?);
+        set(&mut self.decay_frames, profile.decay_frames);
+
+        for (key, key_name) in profile.keys.iter().flatten() {
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| (key as usize) < KEY_COUNT)
+                .ok_or_else(|| format!("Invalid CHIP-8 key: {}.", key))?;
+            self.keys[key as usize] = key_name.clone();
+        }
+
+        if let Some(palette) = &profile.palette {
+            self.colors = Colors::named(palette)?;
+        }
+        if let Some(colors) = &profile.colors {
+            set(&mut self.colors.background, colors.background.as_deref().map(Rgb::parse).transpose()?);
+            set(&mut self.colors.foreground, colors.foreground.as_deref().map(Rgb::parse).transpose()?);
+            set(&mut self.colors.foreground2, colors.foreground2.as_deref().map(Rgb::parse).transpose()?);
+            set(&mut self.colors.blend, colors.blend.as_deref().map(Rgb::parse).transpose()?);
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let settings = &self.settings;
+        if settings.memory_size > 0x10000 {
+            return Err(format!("Memory size exceeds 64 KiB: {}.", settings.memory_size));
+        }
+        if settings.frame_rate == 0 || settings.clock_speed == 0 || settings.stack_depth == 0 || settings.display_width == 0 || settings.display_height == 0 || self.window_scale == 0 || self.screenshot_scale == 0 || self.decay_frames == 0 {
+            return Err("Frame rate, clock speed, stack depth, display size, window scale, screenshot scale and decay frames must be greater than zero.".to_string());
+        }
+        if self.screenshot_scale > MAX_SCREENSHOT_SCALE {
+            return Err(format!("Screenshot scale must be at most {}: {}.", MAX_SCREENSHOT_SCALE, self.screenshot_scale));
+        }
+        // The high resolution display is twice the size, which must fit in a byte.
+        if settings.display_width >= 128 || settings.display_height >= 128 {
+            return Err(format!("Display size must be less than 128x128: {}x{}.", settings.display_width, settings.display_height));
+        }
+        let fonts = [("Sprite", settings.sprite_start_address, FONT_SIZE), ("Large sprite", settings.large_sprite_start_address, LARGE_FONT_SIZE)];
+        for (name, address, size) in fonts {
+            if address as u32 + size > settings.memory_size {
+                return Err(format!("{} data at 0x{:04X} does not fit in memory.", name, address));
+            }
+        }
+        for (key, name) in self.keys.iter().enumerate() {
+            if let Some(other_key) = self.keys[.. key].iter().position(|other_name| other_name.eq_ignore_ascii_case(name)) {
+                return Err(format!("Keyboard key {} is bound to both CHIP-8 keys {:X} and {:X}.", name, other_key, key));
+            }
+            if name.eq_ignore_ascii_case(&self.rewind_key) {
+                return Err(format!("Keyboard key {} is bound to both CHIP-8 key {:X} and rewinding.", name, key));
+            }
+        }
+        Ok(())
+    }
+
+    /// The palettes to cycle through at runtime: the configured colors, followed by the other built-in palettes.
+    pub fn palettes(&self) -> Vec<Colors> {
+        let builtins = PALETTES.iter().map(|&(_, colors)| colors).filter(|&colors| colors != self.colors);
+        [self.colors].into_iter().chain(builtins).collect()
+    }
+
+    /// The number of frames that can be rewound.
+    pub fn rewind_frames(&self) -> usize {
+        self.rewind_seconds as usize * self.settings.frame_rate as usize
+    }
+
+    /// The effective configuration as a profile, with every value specified.
+    pub fn to_profile(&self) -> Profile {
+        let settings = &self.settings;
+        Profile {
+            platform: self.platform.map(|platform| platform.to_string()),
+            clock_speed: Some(settings.clock_speed),
+            frame_rate: Some(settings.frame_rate),
+            memory_size: Some(settings.memory_size),
+            stack_depth: Some(settings.stack_depth),
+            seed: settings.random_seed,
+            display_width: Some(settings.display_width),
+            display_height: Some(settings.display_height),
+            sprite_start_address: Some(settings.sprite_start_address),
+            large_sprite_start_address: Some(settings.large_sprite_start_address),
+            program_start_address: Some(settings.program_start_address),
+            in_place_shift: Some(settings.use_in_place_shift),
+            flexible_jump_offset: Some(settings.use_flexible_jump_offset),
+            auto_address_increments: Some(settings.use_auto_address_increments),
+            flag_reset_on_logic_ops: Some(settings.use_flag_reset_on_logic_ops),
+            sprite_wrapping: Some(settings.use_sprite_wrapping),
+            sprite_draw_delay: Some(settings.use_sprite_draw_delay),
+            large_sprites: Some(settings.use_large_sprites),
+            legacy_low_resolution: Some(settings.use_legacy_low_resolution),
+            frequency: Some(self.speaker_settings.frequency),
+            volume: Some(self.speaker_settings.volume),
+            scale: Some(self.window_scale),
+            rewind_seconds: Some(self.rewind_seconds),
+            rewind_key: Some(self.rewind_key.clone()),
+            screenshot_scale: Some(self.screenshot_scale),
+            persistence: Some(self.persistence.to_string()),
+            decay_frames: Some(self.decay_frames),
+            palette: None,
+            keys: Some(self.keys.iter().enumerate().map(|(key, name)| (format!("{:X}", key), name.clone())).collect()),
+            colors: Some(ColorsProfile {
+                background: Some(self.colors.background.to_string()),
+                foreground: Some(self.colors.foreground.to_string()),
+                foreground2: Some(self.colors.foreground2.to_string()),
+                blend: Some(self.colors.blend.to_string()),
+            }),
+        }
+    }
+
+    /// The effective configuration in the config file format.
+    pub fn to_toml(&self) -> String {
+        let config_file = ConfigFile { defaults: self.to_profile(), roms: BTreeMap::new() };
+        toml::to_string(&config_file).expect("Could not serialize config.")
+    }
+}
+
+fn set<T>(target: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *target = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r##"
+[defaults]
+platform = "chip-48"
+volume = 10
+
+[defaults.keys]
+"0" = "Space"
+
+[defaults.colors]
+foreground = "#33FF66"
+
+[roms."pong.ch8"]
+clock-speed = 600
+sprite-wrapping = true
+persistence = "decay"
+
+[roms."a9993e364706816aba3e25717850c26c9cd0d89d"]
+clock-speed = 700
+"##;
+
+    #[test]
+    fn test_rom_overrides() {
+        let config_file: ConfigFile = toml::from_str(CONFIG).unwrap();
+        let cli = Profile { volume: Some(20), ..Profile::default() };
+        let mut profiles = vec![&config_file.defaults];
+        profiles.extend(config_file.rom_profiles("pong.ch8", "a9993e364706816aba3e25717850c26c9cd0d89d"));
+        profiles.push(&cli);
+        let config = Config::resolve(&profiles).unwrap();
+        assert_eq!(config.platform, Some(Platform::Chip48));
+        assert!(config.settings.use_in_place_shift);
+        assert!(config.settings.use_sprite_wrapping);
+        assert_eq!(config.settings.clock_speed, 700);
+        assert_eq!(config.speaker_settings.volume, 20);
+        assert_eq!(config.keys[0x0], "Space");
+        assert_eq!(config.colors.foreground, Rgb(0x33, 0xFF, 0x66));
+        assert_eq!(config.persistence, Persistence::Decay);
+    }
+
+    #[test]
+    fn test_palettes() {
+        let colors = ColorsProfile { foreground: Some("#FF0000".to_string()), ..ColorsProfile::default() };
+        let profile = Profile { palette: Some("Octo".to_string()), colors: Some(colors), ..Profile::default() };
+        let config = Config::resolve(&[&profile]).unwrap();
+        assert_eq!(config.colors.background, Rgb(0x99, 0x66, 0x00));
+        assert_eq!(config.colors.pixel(1), Rgb(0xFF, 0x00, 0x00));
+        assert_eq!(config.colors.pixel(3), Rgb(0x66, 0x22, 0x00));
+        assert_eq!(config.palettes().len(), PALETTES.len() + 1);
+        assert_eq!(Config::resolve(&[]).unwrap().palettes().len(), PALETTES.len());
+
+        let unknown_palette = Profile { palette: Some("sepia".to_string()), ..Profile::default() };
+        assert!(Config::resolve(&[&unknown_palette]).is_err());
+    }
+
+    #[test]
+    fn test_later_platform_takes_precedence() {
+        let config_file = Profile { platform: Some("chip-48".to_string()), clock_speed: Some(100), ..Profile::default() };
+        let cli = Profile { platform: Some("xo-chip".to_string()), ..Profile::default() };
+        let config = Config::resolve(&[&config_file, &cli]).unwrap();
+        assert_eq!(config.platform, Some(Platform::XoChip));
+        assert_eq!(config.settings.memory_size, 0x10000);
+        assert_eq!(config.settings.clock_speed, 100);
+    }
+
+    #[test]
+    fn test_printed_config_round_trips() {
+        let config = Config::resolve(&[]).unwrap();
+        let config_file: ConfigFile = toml::from_str(&config.to_toml()).unwrap();
+        let reloaded = Config::resolve(&[&config_file.defaults]).unwrap();
+        assert_eq!(reloaded.to_toml(), config.to_toml());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(toml::from_str::<ConfigFile>("[defaults]\nclock-sped = 1").is_err());
+        let invalid_key = Profile { keys: Some(BTreeMap::from([("G".to_string(), "Space".to_string())])), ..Profile::default() };
+        assert!(Config::resolve(&[&invalid_key]).is_err());
+        let zero_stack_depth = Profile { stack_depth: Some(0), ..Profile::default() };
+        assert!(Config::resolve(&[&zero_stack_depth]).is_err());
+        let unknown_persistence = Profile { persistence: Some("glow".to_string()), ..Profile::default() };
+        assert!(Config::resolve(&[&unknown_persistence]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_display_size() {
+        assert!(Config::resolve(&[&Profile { display_width: Some(127), ..Profile::default() }]).is_ok());
+        assert!(Config::resolve(&[&Profile { display_width: Some(128), ..Profile::default() }]).is_err());
+        assert!(Config::resolve(&[&Profile { display_height: Some(200), ..Profile::default() }]).is_err());
+    }
+
+    #[test]
+    fn test_screenshot_scale_limit() {
+        assert!(Config::resolve(&[&Profile { screenshot_scale: Some(16), ..Profile::default() }]).is_ok());
+        assert!(Config::resolve(&[&Profile { screenshot_scale: Some(17), ..Profile::default() }]).is_err());
+        assert!(Config::resolve(&[&Profile { screenshot_scale: Some(u32::MAX), ..Profile::default() }]).is_err());
+    }
+
+    #[test]
+    fn test_font_outside_memory() {
+        let sprites = Profile { sprite_start_address: Some(0xFB0), ..Profile::default() };
+        assert!(Config::resolve(&[&sprites]).is_ok());
+        let sprites = Profile { sprite_start_address: Some(0xFB1), ..Profile::default() };
+        assert!(Config::resolve(&[&sprites]).is_err());
+        let large_sprites = Profile { large_sprite_start_address: Some(0xFF0), ..Profile::default() };
+        assert!(Config::resolve(&[&large_sprites]).is_err());
+        let small_memory = Profile { memory_size: Some(0x40), ..Profile::default() };
+        assert!(Config::resolve(&[&small_memory]).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_key_bindings() {
+        let keys = Profile { keys: Some(BTreeMap::from([("0".to_string(), "q".to_string())])), ..Profile::default() };
+        let error = Config::resolve(&[&keys]).err().unwrap();
+        assert_eq!(error, "Keyboard key Q is bound to both CHIP-8 keys 0 and 4.");
+        let swapped = Profile { keys: Some(BTreeMap::from([("0".to_string(), "Q".to_string()), ("4".to_string(), "X".to_string())])), ..Profile::default() };
+        assert!(Config::resolve(&[&swapped]).is_ok());
+
+        let rewind_key = Profile { rewind_key: Some("x".to_string()), ..Profile::default() };
+        let error = Config::resolve(&[&rewind_key]).err().unwrap();
+        assert_eq!(error, "Keyboard key X is bound to both CHIP-8 key 0 and rewinding.");
+    }
+}
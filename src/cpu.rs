@@ -1,13 +1,80 @@
+use crate::audio::AUDIO_PATTERN_SIZE;
 use crate::instructions::Instruction;
 use crate::settings::Settings;
-use crate::state::State;
+use crate::state::{State, StateError};
+use std::fmt;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CpuCycleResult {
     Continue,
     Wait,
     Done,
 }
 
+/// A fault raised by an instruction, which halts the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuError {
+    /// A memory read or write extended beyond the end of memory.
+    MemoryOutOfBounds { program_counter: u16, instruction: Instruction, address: u16, length: usize },
+    /// A return address or font sprite address was beyond the end of memory.
+    AddressOutOfBounds { program_counter: u16, instruction: Instruction, address: u16 },
+    /// A return was executed with an empty stack.
+    StackUnderflow { program_counter: u16, instruction: Instruction },
+    /// A call was executed with the stack at its maximum depth.
+    StackOverflow { program_counter: u16, instruction: Instruction },
+    /// A key check referred to a key outside the keypad.
+    InvalidKey { program_counter: u16, instruction: Instruction, key: u8 },
+}
+
+impl CpuError {
+    fn from_state_error(error: StateError, program_counter: u16, instruction: Instruction) -> Self {
+        match error {
+            StateError::MemoryOutOfBounds { address, length } => CpuError::MemoryOutOfBounds { program_counter, instruction, address, length },
+            StateError::AddressOutOfBounds { address } => CpuError::AddressOutOfBounds { program_counter, instruction, address },
+            StateError::StackUnderflow => CpuError::StackUnderflow { program_counter, instruction },
+            StateError::StackOverflow => CpuError::StackOverflow { program_counter, instruction },
+            StateError::InvalidSettings(_) => unreachable!("Settings are only checked when the state is created."),
+        }
+    }
+
+    /// The address of the faulting instruction.
+    pub fn program_counter(&self) -> u16 {
+        match *self {
+            CpuError::MemoryOutOfBounds { program_counter, .. } |
+            CpuError::AddressOutOfBounds { program_counter, .. } |
+            CpuError::StackUnderflow { program_counter, .. } |
+            CpuError::StackOverflow { program_counter, .. } |
+            CpuError::InvalidKey { program_counter, .. } => program_counter,
+        }
+    }
+
+    /// The faulting instruction.
+    pub fn instruction(&self) -> Instruction {
+        match *self {
+            CpuError::MemoryOutOfBounds { instruction, .. } |
+            CpuError::AddressOutOfBounds { instruction, .. } |
+            CpuError::StackUnderflow { instruction, .. } |
+            CpuError::StackOverflow { instruction, .. } |
+            CpuError::InvalidKey { instruction, .. } => instruction,
+        }
+    }
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::MemoryOutOfBounds { address, length, .. } => write!(f, "Memory access out of bounds ({} bytes at 0x{:04X})", length, address)?,
+            CpuError::AddressOutOfBounds { address, .. } => write!(f, "Address out of bounds (0x{:04X})", address)?,
+            CpuError::StackUnderflow { .. } => write!(f, "Stack underflow")?,
+            CpuError::StackOverflow { .. } => write!(f, "Stack overflow")?,
+            CpuError::InvalidKey { key, .. } => write!(f, "Invalid key (0x{:02X})", key)?,
+        }
+        write!(f, " at 0x{:04X}: {}.", self.program_counter(), self.instruction())
+    }
+}
+
+impl std::error::Error for CpuError {}
+
 pub struct Cpu {
     settings: Settings,
 }
@@ -15,25 +82,56 @@ pub struct Cpu {
 impl Cpu {
     pub fn new(settings: Settings) -> Self {
         Self {
-            settings: settings,
+            settings,
         }
     }
 
-    pub fn cycle(&self, state: &mut State) -> CpuCycleResult {
+    /// Executes the instruction at the program counter. A fault leaves the program counter past the faulting instruction.
+    pub fn cycle(&self, state: &mut State) -> Result<CpuCycleResult, CpuError> {
         if state.program_terminated() {
-            return CpuCycleResult::Done;
+            return Ok(CpuCycleResult::Done);
         }
 
-        let instruction_bytes = state.read_memory(state.program_counter, 2);
-        let instruction = Instruction::decode((instruction_bytes[0] as u16) << 8 | instruction_bytes[1] as u16);
-        state.increment_program_counter();
+        let program_counter = state.program_counter;
+        let instruction = state.instruction(program_counter);
+        let fault = |error: StateError| CpuError::from_state_error(error, program_counter, instruction);
+        state.read_memory(program_counter, 2).map_err(fault)?;
+        for _ in 0..instruction.size() / 2 {
+            state.increment_program_counter();
+        }
 
         match instruction {
             Instruction::ClearScreen => {
                 state.display.clear();
             },
+            Instruction::ScrollDown { rows } => {
+                let rows = self.scroll_distance(state, rows);
+                state.display.scroll_down(rows);
+            },
+            Instruction::ScrollUp { rows } => {
+                let rows = self.scroll_distance(state, rows);
+                state.display.scroll_up(rows);
+            },
+            Instruction::ScrollRight => {
+                let columns = self.scroll_distance(state, 4);
+                state.display.scroll_right(columns);
+            },
+            Instruction::ScrollLeft => {
+                let columns = self.scroll_distance(state, 4);
+                state.display.scroll_left(columns);
+            },
+            Instruction::Exit => {
+                state.exit();
+                return Ok(CpuCycleResult::Done);
+            },
+            Instruction::LowResolution => {
+                state.display.set_high_resolution(false);
+            },
+            Instruction::HighResolution => {
+                state.display.set_high_resolution(true);
+            },
             Instruction::Return => {
-                let return_address = state.pop_return_address();
+                let return_address = state.pop_return_address().map_err(fault)?;
                 state.program_counter = return_address;
             },
             Instruction::Jump { address } => {
@@ -49,33 +147,44 @@ impl Cpu {
                 state.program_counter = address + offset as u16;
             },
             Instruction::Call { address } => {
-                state.push_return_address(state.program_counter);
+                state.push_return_address(state.program_counter).map_err(fault)?;
                 state.program_counter = address;
             },
             Instruction::SkipIfValue { register, comparand_value } => {
                 let value = state.register(register);
                 if value == comparand_value {
-                    state.increment_program_counter()
+                    state.skip_instruction();
                 }
             },
             Instruction::SkipIfNotValue { register, comparand_value } => {
                 let value = state.register(register);
                 if value != comparand_value {
-                    state.increment_program_counter();
+                    state.skip_instruction();
                 }
             },
             Instruction::SkipIfEqual { register, comparand_register } => {
                 let value = state.register(register);
                 let comparand_value = state.register(comparand_register);
                 if value == comparand_value {
-                    state.increment_program_counter();
+                    state.skip_instruction();
                 }
             },
             Instruction::SkipIfNotEqual { register, comparand_register } => {
                 let value = state.register(register);
                 let comparand_value = state.register(comparand_register);
                 if value != comparand_value {
-                    state.increment_program_counter();
+                    state.skip_instruction();
+                }
+            },
+            Instruction::WriteRegisterRange { register, end_register } => {
+                let data = Self::register_range(state, register, end_register);
+                state.write_memory(state.address_register(), &data).map_err(fault)?;
+            },
+            Instruction::ReadRegisterRange { register, end_register } => {
+                let count = register.abs_diff(end_register) as u16 + 1;
+                let data = state.read_memory(state.address_register(), count).map_err(fault)?.to_vec();
+                for (register, value) in Self::register_range_indices(register, end_register).zip(data) {
+                    state.set_register(register, value);
                 }
             },
             Instruction::LoadValue { register, value } => {
@@ -151,23 +260,37 @@ impl Cpu {
                 state.set_register(register, value << 1);
                 state.set_register(0xF, (value & 0x80) >> 7);
             },
+            Instruction::LoadLongAddress { address } => {
+                state.set_address_register(address);
+            },
+            Instruction::SelectPlanes { planes } => {
+                state.display.select_planes(planes);
+            },
+            Instruction::LoadAudioPattern => {
+                let mut buffer = [0x0; AUDIO_PATTERN_SIZE];
+                buffer.copy_from_slice(state.read_memory(state.address_register(), AUDIO_PATTERN_SIZE as u16).map_err(fault)?);
+                state.audio_buffer = Some(buffer);
+            },
+            Instruction::SetPitch { register } => {
+                state.pitch = state.register(register);
+            },
             Instruction::LoadAddress { address } => {
                 state.set_address_register(address);
             },
             Instruction::Random { register, mask } => {
-                let random_value: u8 = rand::random();
+                let random_value = state.random_byte();
                 state.set_register(register, random_value & mask);
             },
             Instruction::SkipIfKeyDown { register } => {
-                let value = state.register(register);
+                let value = Self::key(state, register, program_counter, instruction)?;
                 if state.keypad.key_pressed(value) {
-                    state.increment_program_counter();
+                    state.skip_instruction();
                 }
             },
             Instruction::SkipIfKeyUp { register } => {
-                let value = state.register(register);
+                let value = Self::key(state, register, program_counter, instruction)?;
                 if !state.keypad.key_pressed(value) {
-                    state.increment_program_counter();
+                    state.skip_instruction();
                 }
             },
             Instruction::WaitForKeyDown { register } => {
@@ -175,7 +298,7 @@ impl Cpu {
                     state.set_register(register, *key);
                 } else {
                     state.decrement_program_counter();
-                    return CpuCycleResult::Wait;
+                    return Ok(CpuCycleResult::Wait);
                 }
             }
             Instruction::LoadDelayTimer { register } => {
@@ -189,65 +312,135 @@ impl Cpu {
             },
             Instruction::AddToAddress { register } => {
                 let value_to_add = state.register(register) as u16;
-                let address = state.address_register() + value_to_add;
+                let address = state.address_register().wrapping_add(value_to_add);
                 state.set_address_register(address);
             },
             Instruction::WriteMemoryFromBinaryCodedDecimal { register } => {
                 let value = state.register(register);
                 let bcd_data = vec![value / 100, (value % 100) / 10, value % 10];
-                state.write_memory(state.address_register(), &bcd_data)
+                state.write_memory(state.address_register(), &bcd_data).map_err(fault)?;
             },
             Instruction::WriteMemory { end_register } => {
                 let address = state.address_register();
                 let data = state.read_registers(end_register).to_vec();
-                state.write_memory(address, &data);
+                state.write_memory(address, &data).map_err(fault)?;
                 if self.settings.use_auto_address_increments {
-                    state.set_address_register(address + end_register as u16 + 1);
+                    state.set_address_register(address.wrapping_add(end_register as u16 + 1));
                 }
             },
             Instruction::ReadMemory { end_register } => {
                 let address = state.address_register();
-                let data = state.read_memory(address, end_register as u16 + 1).to_vec();
+                let data = state.read_memory(address, end_register as u16 + 1).map_err(fault)?.to_vec();
                 state.write_registers(&data);
                 if self.settings.use_auto_address_increments {
-                    state.set_address_register(address + end_register as u16 + 1);
+                    state.set_address_register(address.wrapping_add(end_register as u16 + 1));
                 }
             },
             Instruction::LoadDigitSpriteAddress { register } => {
                 let digit = state.register(register) as u16;
-                state.set_address_register(self.settings.sprite_start_address + (digit & 0x0F) * 5);
+                let address = Self::font_address(self.settings.sprite_start_address, (digit & 0x0F) * 5).map_err(fault)?;
+                state.set_address_register(address);
+            },
+            Instruction::LoadLargeDigitSpriteAddress { register } => {
+                let digit = state.register(register) as u16;
+                let address = Self::font_address(self.settings.large_sprite_start_address, (digit & 0x0F) * 10).map_err(fault)?;
+                state.set_address_register(address);
+            },
+            Instruction::WriteFlags { end_register } => {
+                let data = state.read_registers(end_register).to_vec();
+                state.write_flags(&data);
+            },
+            Instruction::ReadFlags { end_register } => {
+                let data = state.read_flags(end_register).to_vec();
+                state.write_registers(&data);
+            },
+            Instruction::DrawLargeSprite { register_x, register_y } => {
+                let x = state.register(register_x);
+                let y = state.register(register_y);
+                let legacy_low_resolution = self.settings.use_legacy_low_resolution && !state.display.high_resolution();
+                let size = match (self.settings.use_large_sprites, legacy_low_resolution) {
+                    (false, _) => 0,
+                    (true, true) => 16,
+                    (true, false) => 32,
+                } * state.display.selected_plane_count() as u16;
+                let sprite = state.read_memory(state.address_register(), size).map_err(fault)?.to_vec();
+                let pixels_hidden = if legacy_low_resolution {
+                    state.display.apply_sprite(x, y, &sprite)
+                } else {
+                    state.display.apply_large_sprite(x, y, &sprite)
+                };
+                state.set_register(0xF, pixels_hidden as u8);
+                if self.settings.use_sprite_draw_delay {
+                    return Ok(CpuCycleResult::Wait);
+                }
             },
             Instruction::DrawSprite { register_x, register_y, length } => {
                 let x = state.register(register_x);
                 let y = state.register(register_y);
-                let sprite = state.read_memory(state.address_register(), length as u16).to_vec();
+                let size = length as u16 * state.display.selected_plane_count() as u16;
+                let sprite = state.read_memory(state.address_register(), size).map_err(fault)?.to_vec();
                 let pixels_hidden = state.display.apply_sprite(x, y, &sprite);
                 state.set_register(0xF, pixels_hidden as u8);
                 if self.settings.use_sprite_draw_delay {
-                    return CpuCycleResult::Wait;
+                    return Ok(CpuCycleResult::Wait);
                 }
             },
             Instruction::SysCall { .. } | Instruction::Unknown { .. } => { }
         }
 
-        return CpuCycleResult::Continue;
+        Ok(CpuCycleResult::Continue)
+    }
+
+    /// The distance to scroll by, which SUPER-CHIP 1.1 gives in high resolution pixels, even in low resolution mode.
+    fn scroll_distance(&self, state: &State, distance: u8) -> u8 {
+        if self.settings.use_legacy_low_resolution && !state.display.high_resolution() {
+            distance / 2
+        } else {
+            distance
+        }
+    }
+
+    /// The address of a font sprite, given the offset of its digit from the start of the font.
+    fn font_address(start_address: u16, offset: u16) -> Result<u16, StateError> {
+        start_address.checked_add(offset).ok_or(StateError::AddressOutOfBounds { address: start_address })
+    }
+
+    /// The key held in the register, which must be on the keypad.
+    fn key(state: &State, register: u8, program_counter: u16, instruction: Instruction) -> Result<u8, CpuError> {
+        let key = state.register(register);
+        if key > 0xF {
+            return Err(CpuError::InvalidKey { program_counter, instruction, key });
+        }
+        Ok(key)
+    }
+
+    /// The registers VX - VY, in descending order if Y is less than X.
+    fn register_range_indices(register: u8, end_register: u8) -> Box<dyn Iterator<Item = u8>> {
+        if register <= end_register {
+            Box::new(register..=end_register)
+        } else {
+            Box::new((end_register..=register).rev())
+        }
+    }
+
+    fn register_range(state: &State, register: u8, end_register: u8) -> Vec<u8> {
+        Self::register_range_indices(register, end_register).map(|register| state.register(register)).collect()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::settings::Platform;
 
     fn setup(instructions: Vec<Instruction>) -> (Cpu, State, Settings) {
         let mut program = vec![];
         for instruction in instructions {
-            let instruction_bytes = instruction.encode();
-            program.push(((instruction_bytes & 0xFF00) >> 8) as u8);
-            program.push((instruction_bytes & 0x00FF) as u8);
+            program.extend(instruction.encode_bytes());
         }
         let settings = Settings::default();
         let cpu = Cpu::new(settings);
-        let state = State::new(settings, program);
+        let state = State::new(settings, program).unwrap();
         (cpu, state, settings)
     }
 
@@ -257,7 +450,7 @@ mod tests {
         let (cpu, mut state, _) = setup(program);
         state.set_register(0x0, 0x11);
         state.set_register(0x1, 0x10);
-        let _ = cpu.cycle(&mut state);
+        cpu.cycle(&mut state).unwrap();
         assert_eq!(state.register(0x0), 0x11 + 0x10);
         assert_eq!(state.register(0xF), 0x00);
     }
@@ -268,7 +461,7 @@ mod tests {
         let (cpu, mut state, _) = setup(program);
         state.set_register(0x0, 0xFF);
         state.set_register(0x1, 0x01);
-        let _ = cpu.cycle(&mut state);
+        cpu.cycle(&mut state).unwrap();
         assert_eq!(state.register(0x0), 0x00);
         assert_eq!(state.register(0xF), 0x01);
     }
@@ -279,7 +472,7 @@ mod tests {
         let (cpu, mut state, _) = setup(program);
         state.set_register(0x0, 0x11);
         state.set_register(0x1, 0x10);
-        let _ = cpu.cycle(&mut state);
+        cpu.cycle(&mut state).unwrap();
         assert_eq!(state.register(0x0), 0x11 - 0x10);
         assert_eq!(state.register(0xF), 0x01);
     }
@@ -290,7 +483,7 @@ mod tests {
         let (cpu, mut state, _) = setup(program);
         state.set_register(0x0, 0x10);
         state.set_register(0x1, 0x11);
-        let _ = cpu.cycle(&mut state);
+        cpu.cycle(&mut state).unwrap();
         assert_eq!(state.register(0x0), 0xFF);
         assert_eq!(state.register(0xF), 0x00);
     }
@@ -301,7 +494,7 @@ mod tests {
         let (cpu, mut state, _) = setup(program);
         state.set_register(0x0, 0x10);
         state.set_register(0x1, 0x11);
-        let _ = cpu.cycle(&mut state);
+        cpu.cycle(&mut state).unwrap();
         assert_eq!(state.register(0x0), 0x11 - 0x10);
         assert_eq!(state.register(0xF), 0x01);
     }
@@ -312,7 +505,7 @@ mod tests {
         let (cpu, mut state, _) = setup(program);
         state.set_register(0x0, 0x11);
         state.set_register(0x1, 0x10);
-        let _ = cpu.cycle(&mut state);
+        cpu.cycle(&mut state).unwrap();
         assert_eq!(state.register(0x0), 0xFF);
         assert_eq!(state.register(0xF), 0x00);
     }
@@ -323,8 +516,8 @@ mod tests {
         let (cpu, mut state, _) = setup(program);
         state.set_register(0x0, 123);
         state.set_address_register(0x0400);
-        let _ = cpu.cycle(&mut state);
-        assert_eq!(state.read_memory(0x0400, 3), &[1, 2, 3]);
+        cpu.cycle(&mut state).unwrap();
+        assert_eq!(state.read_memory(0x0400, 3).unwrap(), &[1, 2, 3]);
     }
 
     #[test]
@@ -332,7 +525,7 @@ mod tests {
         let program = vec![ Instruction::SkipIfValue { register: 0x0, comparand_value: 0x11 } ];
         let (cpu, mut state, settings) = setup(program);
         state.set_register(0x0, 0x11);
-        let _ = cpu.cycle(&mut state);
+        cpu.cycle(&mut state).unwrap();
         assert_eq!(state.program_counter, settings.program_start_address + 4);
     }
 
@@ -341,7 +534,231 @@ mod tests {
         let program = vec![ Instruction::SkipIfValue { register: 0x0, comparand_value: 0x11 } ];
         let (cpu, mut state, settings) = setup(program);
         state.set_register(0x0, 0x10);
-        let _ = cpu.cycle(&mut state);
+        cpu.cycle(&mut state).unwrap();
         assert_eq!(state.program_counter, settings.program_start_address + 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_exit() {
+        let program = vec![ Instruction::Exit ];
+        let (cpu, mut state, _) = setup(program);
+        assert_eq!(cpu.cycle(&mut state), Ok(CpuCycleResult::Done));
+        assert!(state.program_terminated());
+    }
+
+    #[test]
+    fn test_high_resolution() {
+        let program = vec![ Instruction::HighResolution, Instruction::LowResolution ];
+        let (cpu, mut state, settings) = setup(program);
+        cpu.cycle(&mut state).unwrap();
+        assert_eq!(state.display.width, settings.display_width * 2);
+        assert_eq!(state.display.height, settings.display_height * 2);
+        cpu.cycle(&mut state).unwrap();
+        assert_eq!(state.display.width, settings.display_width);
+        assert_eq!(state.display.height, settings.display_height);
+    }
+
+    #[test]
+    fn test_scroll_down() {
+        let program = vec![ Instruction::ScrollDown { rows: 0x3 } ];
+        let (cpu, mut state, _) = setup(program);
+        state.display.apply_sprite(0, 0, &[0x80]);
+        cpu.cycle(&mut state).unwrap();
+        assert_eq!(state.display.visible_pixels(), vec![(0, 3)]);
+    }
+
+    #[test]
+    fn test_draw_large_sprite() {
+        let program = vec![ Instruction::DrawLargeSprite { register_x: 0x0, register_y: 0x1 } ];
+        let (cpu, mut state, _) = setup(program);
+        state.write_memory(0x0400, &[0xFF; 32]).unwrap();
+        state.set_address_register(0x0400);
+        cpu.cycle(&mut state).unwrap();
+        assert_eq!(state.display.visible_pixels().len(), 16 * 16);
+        assert_eq!(state.register(0xF), 0x00);
+    }
+
+    #[test]
+    fn test_draw_large_sprite_by_platform() {
+        let pixels_drawn = |platform: Platform, high_resolution: bool| {
+            let settings = Settings::preset(platform);
+            let cpu = Cpu::new(settings);
+            let mut state = State::new(settings, Instruction::DrawLargeSprite { register_x: 0x0, register_y: 0x0 }.encode_bytes()).unwrap();
+            state.display.set_high_resolution(high_resolution);
+            state.write_memory(0x0400, &[0xFF; 32]).unwrap();
+            state.set_address_register(0x0400);
+            cpu.cycle(&mut state).unwrap();
+            state.display.visible_pixels().len()
+        };
+        assert_eq!(pixels_drawn(Platform::CosmacVip, false), 0);
+        assert_eq!(pixels_drawn(Platform::Chip48, false), 0);
+        assert_eq!(pixels_drawn(Platform::SuperChip11, false), 8 * 16);
+        assert_eq!(pixels_drawn(Platform::SuperChip11, true), 16 * 16);
+        assert_eq!(pixels_drawn(Platform::XoChip, false), 16 * 16);
+    }
+
+    #[test]
+    fn test_legacy_low_resolution_scroll() {
+        let settings = Settings::preset(Platform::SuperChip11);
+        let cpu = Cpu::new(settings);
+        let program = [Instruction::ScrollDown { rows: 0x4 }, Instruction::ScrollRight, Instruction::HighResolution, Instruction::ScrollDown { rows: 0x4 }];
+        let mut state = State::new(settings, program.into_iter().flat_map(Instruction::encode_bytes).collect()).unwrap();
+        state.display.apply_sprite(0, 0, &[0x80]);
+        cpu.cycle(&mut state).unwrap();
+        cpu.cycle(&mut state).unwrap();
+        assert_eq!(state.display.visible_pixels(), vec![(2, 2)]);
+        cpu.cycle(&mut state).unwrap();
+        state.display.apply_sprite(0, 0, &[0x80]);
+        cpu.cycle(&mut state).unwrap();
+        assert_eq!(state.display.visible_pixels(), vec![(0, 4)]);
+    }
+
+    #[test]
+    fn test_load_long_address() {
+        let program = vec![ Instruction::LoadLongAddress { address: 0x0FED } ];
+        let (cpu, mut state, settings) = setup(program);
+        cpu.cycle(&mut state).unwrap();
+        assert_eq!(state.address_register(), 0x0FED);
+        assert_eq!(state.program_counter, settings.program_start_address + 4);
+    }
+
+    #[test]
+    fn test_skip_long_instruction() {
+        let program = vec![
+            Instruction::SkipIfValue { register: 0x0, comparand_value: 0x00 },
+            Instruction::LoadLongAddress { address: 0x1234 },
+        ];
+        let (cpu, mut state, settings) = setup(program);
+        cpu.cycle(&mut state).unwrap();
+        assert_eq!(state.program_counter, settings.program_start_address + 6);
+    }
+
+    #[test]
+    fn test_register_range_reversed() {
+        let program = vec![ Instruction::WriteRegisterRange { register: 0x3, end_register: 0x1 } ];
+        let (cpu, mut state, _) = setup(program);
+        state.write_registers(&[0x00, 0x11, 0x22, 0x33]);
+        state.set_address_register(0x0400);
+        cpu.cycle(&mut state).unwrap();
+        assert_eq!(state.read_memory(0x0400, 3).unwrap(), &[0x33, 0x22, 0x11]);
+        assert_eq!(state.address_register(), 0x0400);
+    }
+
+    #[test]
+    fn test_draw_sprite_on_both_planes() {
+        let program = vec![
+            Instruction::SelectPlanes { planes: 0x3 },
+            Instruction::DrawSprite { register_x: 0x0, register_y: 0x0, length: 1 },
+        ];
+        let (cpu, mut state, _) = setup(program);
+        state.write_memory(0x0400, &[0x80, 0x40]).unwrap();
+        state.set_address_register(0x0400);
+        cpu.cycle(&mut state).unwrap();
+        cpu.cycle(&mut state).unwrap();
+        assert_eq!(state.display.pixel(0, 0), 0x1);
+        assert_eq!(state.display.pixel(1, 0), 0x2);
+    }
+
+    #[test]
+    fn test_flags_round_trip() {
+        let program = vec![
+            Instruction::WriteFlags { end_register: 0x2 },
+            Instruction::LoadValue { register: 0x1, value: 0x00 },
+            Instruction::ReadFlags { end_register: 0x2 },
+        ];
+        let (cpu, mut state, _) = setup(program);
+        state.write_registers(&[0x11, 0x22, 0x33]);
+        for _ in 0..3 {
+            cpu.cycle(&mut state).unwrap();
+        }
+        assert_eq!(state.read_registers(0x2), &[0x11, 0x22, 0x33]);
+    }
+
+    #[test]
+    fn test_stack_underflow_fault() {
+        let program = vec![ Instruction::Return ];
+        let (cpu, mut state, _) = setup(program);
+        let error = cpu.cycle(&mut state).unwrap_err();
+        assert_eq!(error, CpuError::StackUnderflow { program_counter: 0x200, instruction: Instruction::Return });
+        assert_eq!(error.to_string(), "Stack underflow at 0x0200: RET.");
+    }
+
+    #[test]
+    fn test_memory_out_of_bounds_fault() {
+        let program = vec![
+            Instruction::LoadAddress { address: 0xFFE },
+            Instruction::DrawSprite { register_x: 0x0, register_y: 0x0, length: 4 },
+        ];
+        let (cpu, mut state, _) = setup(program);
+        cpu.cycle(&mut state).unwrap();
+        let error = cpu.cycle(&mut state).unwrap_err();
+        assert_eq!(error.program_counter(), 0x202);
+        assert!(matches!(error, CpuError::MemoryOutOfBounds { address: 0xFFE, length: 4, .. }));
+    }
+
+    #[test]
+    fn test_address_past_end_of_memory() {
+        let program = vec![
+            Instruction::LoadAddress { address: 0xFFE },
+            Instruction::WriteMemory { end_register: 0x1 },
+            Instruction::AddToAddress { register: 0x0 },
+            Instruction::ReadMemory { end_register: 0x0 },
+        ];
+        let settings = Settings { use_auto_address_increments: true, ..Settings::default() };
+        let cpu = Cpu::new(settings);
+        let mut state = State::new(settings, program.into_iter().flat_map(Instruction::encode_bytes).collect()).unwrap();
+        state.set_register(0x0, 0x10);
+        for _ in 0..3 {
+            cpu.cycle(&mut state).unwrap();
+        }
+        assert_eq!(state.read_memory(0xFFE, 2).unwrap(), &[0x10, 0x00]);
+        assert_eq!(state.address_register(), 0x1010);
+        assert!(matches!(cpu.cycle(&mut state), Err(CpuError::MemoryOutOfBounds { address: 0x1010, length: 1, .. })));
+    }
+
+    #[test]
+    fn test_font_address_overflow_fault() {
+        let settings = Settings { sprite_start_address: 0xFFF0, memory_size: 0x10000, ..Settings::default() };
+        let cpu = Cpu::new(settings);
+        let mut state = State::new(Settings { sprite_start_address: 0x0, ..settings }, vec![]).unwrap();
+        state.set_register(0x0, 0xF);
+        state.write_memory(0x200, &Instruction::LoadDigitSpriteAddress { register: 0x0 }.encode_bytes()).unwrap();
+        assert!(matches!(cpu.cycle(&mut state), Err(CpuError::AddressOutOfBounds { address: 0xFFF0, .. })));
+    }
+
+    #[test]
+    fn test_invalid_key_fault() {
+        let program = vec![ Instruction::SkipIfKeyDown { register: 0x0 } ];
+        let (cpu, mut state, _) = setup(program);
+        state.set_register(0x0, 0x10);
+        assert!(matches!(cpu.cycle(&mut state), Err(CpuError::InvalidKey { key: 0x10, .. })));
+    }
+
+    #[test]
+    fn test_stack_overflow_fault() {
+        let settings = Settings { stack_depth: 2, ..Settings::default() };
+        let cpu = Cpu::new(settings);
+        let mut state = State::new(settings, Instruction::Call { address: 0x200 }.encode_bytes()).unwrap();
+        cpu.cycle(&mut state).unwrap();
+        cpu.cycle(&mut state).unwrap();
+        let error = cpu.cycle(&mut state).unwrap_err();
+        assert_eq!(error, CpuError::StackOverflow { program_counter: 0x200, instruction: Instruction::Call { address: 0x200 } });
+        assert_eq!(state.stack(), &[0x202, 0x202]);
+    }
+
+    #[test]
+    fn test_random_is_reproducible_with_seed() {
+        let settings = Settings { random_seed: Some(1234), ..Settings::default() };
+        let program: Vec<u8> = (0..8).flat_map(|register| Instruction::Random { register, mask: 0xFF }.encode_bytes()).collect();
+        let run = || {
+            let cpu = Cpu::new(settings);
+            let mut state = State::new(settings, program.clone()).unwrap();
+            for _ in 0..8 {
+                cpu.cycle(&mut state).unwrap();
+            }
+            state.read_registers(0x7).to_vec()
+        };
+        assert_eq!(run(), run());
+        assert_ne!(run(), vec![run()[0]; 8]);
+    }
+}
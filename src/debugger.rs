@@ -0,0 +1,434 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use crate::cpu::{CpuCycleResult, CpuError};
+use crate::emulator::{Emulator, EmulatorInput, EmulatorOutput};
+use crate::instructions::Instruction;
+use crate::state::State;
+
+const DEFAULT_HEXDUMP_LENGTH: u16 = 64;
+const HEXDUMP_ROW_LENGTH: usize = 16;
+
+pub const HELP: &str = "\
+step, s                   Execute a single instruction
+next, n                   Execute a single instruction, running subroutine calls to completion
+finish, f                 Run until the current subroutine returns
+continue, c               Continue running until a breakpoint is hit
+pause, p                  Pause execution
+break, b <ADDRESS>        Set a breakpoint
+delete, d <ADDRESS>       Remove a breakpoint
+breakpoints, bl           List breakpoints
+registers, r              Print V0-VF, I, PC and timers
+stack, bt                 Print the return addresses on the stack
+memory, x <ADDRESS> [LEN] Print a hexdump of memory
+help, h                   Print this help
+
+Numeric values may be given in decimal or, prefixed with 0x, in hexadecimal.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebuggerCommand {
+    Step,
+    StepOver,
+    StepOut,
+    Continue,
+    Pause,
+    Break { address: u16 },
+    Delete { address: u16 },
+    Breakpoints,
+    Registers,
+    Stack,
+    Memory { address: u16, length: u16 },
+    Help,
+}
+
+impl FromStr for DebuggerCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let argument = |index: usize| -> Result<u16, String> {
+            let value = words.get(index).ok_or_else(|| format!("Missing argument for {}.", words[0]))?;
+            let number = match value.strip_prefix("0x") {
+                Some(hex) => u16::from_str_radix(hex, 16),
+                None => value.parse::<u16>(),
+            };
+            number.map_err(|_| format!("Invalid number: {}.", value))
+        };
+
+        let command = match words.first().copied() {
+            Some("step" | "s") => Self::Step,
+            Some("next" | "n") => Self::StepOver,
+            Some("finish" | "f") => Self::StepOut,
+            Some("continue" | "c") => Self::Continue,
+            Some("pause" | "p") => Self::Pause,
+            Some("break" | "b") => Self::Break { address: argument(1)? },
+            Some("delete" | "d") => Self::Delete { address: argument(1)? },
+            Some("breakpoints" | "bl") => Self::Breakpoints,
+            Some("registers" | "r") => Self::Registers,
+            Some("stack" | "bt") => Self::Stack,
+            Some("memory" | "x") => {
+                let length = if words.len() > 2 { argument(2)? } else { DEFAULT_HEXDUMP_LENGTH };
+                Self::Memory { address: argument(1)?, length }
+            },
+            Some("help" | "h") => Self::Help,
+            Some(command) => return Err(format!("Unknown command: {}. Type help for a list of commands.", command)),
+            None => return Err("Missing command.".to_string()),
+        };
+        Ok(command)
+    }
+}
+
+/// The reason the debugger paused a running program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// A breakpoint was hit at the given address.
+    Breakpoint(u16),
+    /// Stepping over a subroutine call or out of a subroutine completed at the given address.
+    Step(u16),
+    /// The program exited or ran out of memory.
+    Halted,
+    /// The program was halted by a fault.
+    Fault(CpuError),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint(address) => write!(f, "Breakpoint hit at 0x{:04X}.", address),
+            StopReason::Step(address) => write!(f, "Stopped at 0x{:04X}.", address),
+            StopReason::Halted => write!(f, "Program halted."),
+            StopReason::Fault(error) => write!(f, "Program halted by fault: {}", error),
+        }
+    }
+}
+
+/// A condition for pausing a running program, other than breakpoints.
+#[derive(Clone, Copy)]
+enum Target {
+    /// Pause once execution returns to the address with the stack at the given depth.
+    Return { address: u16, depth: usize },
+    /// Pause once the stack is shallower than the given depth.
+    StackDepth(usize),
+}
+
+impl Target {
+    fn reached(self, state: &State) -> bool {
+        match self {
+            Target::Return { address, depth } => state.program_counter == address && state.stack().len() == depth,
+            Target::StackDepth(depth) => state.stack().len() < depth,
+        }
+    }
+}
+
+/// Controls the execution of an emulator with breakpoints and stepping, independent of any frontend.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    paused: bool,
+    target: Option<Target>,
+    /// Whether to ignore a breakpoint at the program counter, so that resuming does not immediately hit it again.
+    leaving_breakpoint: bool,
+}
+
+impl Debugger {
+    /// Creates a debugger with the program paused.
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            paused: true,
+            target: None,
+            leaving_breakpoint: false,
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.target = None;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.leaving_breakpoint = true;
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Pauses the program and executes a single instruction, without updating timers or input.
+    pub fn step(&mut self, emulator: &mut Emulator) -> Result<CpuCycleResult, CpuError> {
+        self.pause();
+        emulator.step_cycle()
+    }
+
+    /// Executes a single instruction, or resumes until a subroutine call at the program counter returns.
+    pub fn step_over(&mut self, emulator: &mut Emulator) -> Result<(), CpuError> {
+        let state = emulator.state();
+        let instruction = state.instruction(state.program_counter);
+        if let Instruction::Call { .. } = instruction {
+            let address = state.program_counter.wrapping_add(instruction.size());
+            self.resume();
+            self.target = Some(Target::Return { address, depth: state.stack().len() });
+        } else {
+            self.step(emulator)?;
+        }
+        Ok(())
+    }
+
+    /// Resumes until the current subroutine returns.
+    pub fn step_out(&mut self, emulator: &Emulator) -> Result<(), String> {
+        let depth = emulator.state().stack().len();
+        if depth == 0 {
+            return Err("Not inside a subroutine.".to_string());
+        }
+        self.resume();
+        self.target = Some(Target::StackDepth(depth));
+        Ok(())
+    }
+
+    /// Runs a frame unless the program is paused, pausing it when a breakpoint or stepping target is reached.
+    pub fn run_frame(&mut self, emulator: &mut Emulator, input: EmulatorInput) -> (EmulatorOutput, Option<StopReason>) {
+        if self.paused {
+            emulator.state_mut().keypad = input.keypad;
+            return (emulator.output(), None);
+        }
+
+        let breakpoints = &self.breakpoints;
+        let target = self.target;
+        let mut leaving_breakpoint = self.leaving_breakpoint;
+        let mut reason = None;
+        let (output, _) = emulator.run_frame_until(input, |state| {
+            let address = state.program_counter;
+            if target.is_some_and(|target| target.reached(state)) {
+                reason = Some(StopReason::Step(address));
+            } else if !leaving_breakpoint && breakpoints.contains(&address) {
+                reason = Some(StopReason::Breakpoint(address));
+            }
+            leaving_breakpoint = false;
+            reason.is_some()
+        });
+        self.leaving_breakpoint = leaving_breakpoint;
+
+        if reason.is_none() && output.halted {
+            reason = Some(output.fault.map_or(StopReason::Halted, StopReason::Fault));
+        }
+        if reason.is_some() {
+            self.pause();
+        }
+        (output, reason)
+    }
+
+    /// Executes a command, yielding the text to show to the user.
+    pub fn execute(&mut self, emulator: &mut Emulator, command: DebuggerCommand) -> Result<String, String> {
+        let output = match command {
+            DebuggerCommand::Step => {
+                self.step(emulator).map_err(|error| error.to_string())?;
+                location(emulator.state())
+            },
+            DebuggerCommand::StepOver => {
+                self.step_over(emulator).map_err(|error| error.to_string())?;
+                if self.paused { location(emulator.state()) } else { String::new() }
+            },
+            DebuggerCommand::StepOut => {
+                self.step_out(emulator)?;
+                String::new()
+            },
+            DebuggerCommand::Continue => {
+                self.resume();
+                String::new()
+            },
+            DebuggerCommand::Pause => {
+                self.pause();
+                location(emulator.state())
+            },
+            DebuggerCommand::Break { address } => {
+                self.add_breakpoint(address);
+                format!("Breakpoint set at 0x{:04X}.", address)
+            },
+            DebuggerCommand::Delete { address } => {
+                if !self.remove_breakpoint(address) {
+                    return Err(format!("No breakpoint at 0x{:04X}.", address));
+                }
+                format!("Breakpoint removed at 0x{:04X}.", address)
+            },
+            DebuggerCommand::Breakpoints => {
+                if self.breakpoints.is_empty() {
+                    "No breakpoints.".to_string()
+                } else {
+                    let addresses: Vec<String> = self.breakpoints.iter().map(|address| format!("0x{:04X}", address)).collect();
+                    addresses.join("\n")
+                }
+            },
+            DebuggerCommand::Registers => registers(emulator.state()),
+            DebuggerCommand::Stack => stack(emulator.state()),
+            DebuggerCommand::Memory { address, length } => hexdump(emulator.state(), address, length),
+            DebuggerCommand::Help => HELP.to_string(),
+        };
+        Ok(output)
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The program counter and the instruction at it.
+pub fn location(state: &State) -> String {
+    let address = state.program_counter;
+    let instruction = state.instruction(address);
+    let opcode: Vec<String> = (0..instruction.size() / 2).map(|word| format!("{:04X}", state.read_word(address.wrapping_add(word * 2)))).collect();
+    format!("0x{:04X}: {:<10}{}", address, opcode.join(" "), instruction.format(&state.settings()))
+}
+
+/// The registers, address register, program counter and timers.
+pub fn registers(state: &State) -> String {
+    let registers: Vec<String> = (0..16).map(|register| format!("V{:X}: {:02X}", register, state.register(register))).collect();
+    format!(
+        "{}\n{}\nI: 0x{:04X}  PC: 0x{:04X}  DT: {}  ST: {}",
+        registers[.. 8].join("  "),
+        registers[8 ..].join("  "),
+        state.address_register(),
+        state.program_counter,
+        state.delay_register,
+        state.sound_register,
+    )
+}
+
+/// A stack trace of the return addresses on the stack, innermost first, along with the calls that pushed them.
+pub fn stack(state: &State) -> String {
+    if state.stack().is_empty() {
+        return "Stack is empty.".to_string();
+    }
+    let mut lines = vec![format!("Stack depth {} of {}:", state.stack().len(), state.settings().stack_depth)];
+    lines.extend(state.stack().iter().rev().enumerate().map(|(depth, &address)| {
+        // Calls are always two bytes long, so the call site directly precedes the return address.
+        format!("#{} 0x{:04X} (called from 0x{:04X})", depth, address, address.wrapping_sub(2))
+    }));
+    lines.join("\n")
+}
+
+/// A hexdump of memory, clipped to the memory size.
+pub fn hexdump(state: &State, address: u16, length: u16) -> String {
+    let memory_size = state.settings().memory_size;
+    let end = (address as u32 + length as u32).min(memory_size);
+    if address as u32 >= end {
+        return format!("Address out of bounds: 0x{:04X}.", address);
+    }
+    let data = state.read_memory(address, (end - address as u32) as u16).expect("Could not read clipped memory range.");
+    let rows: Vec<String> = data
+        .chunks(HEXDUMP_ROW_LENGTH)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("0x{:04X}: {}", address as usize + row * HEXDUMP_ROW_LENGTH, bytes.join(" "))
+        })
+        .collect();
+    rows.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    fn setup(instructions: Vec<Instruction>) -> (Emulator, Debugger) {
+        let mut program = vec![];
+        for instruction in instructions {
+            program.extend(instruction.encode_bytes());
+        }
+        (Emulator::new(Settings::default(), program).unwrap(), Debugger::new())
+    }
+
+    fn subroutine_program() -> Vec<Instruction> {
+        vec![
+            Instruction::Call { address: 0x206 },
+            Instruction::AddValue { register: 0x1, value: 0x01 },
+            Instruction::Jump { address: 0x202 },
+            Instruction::AddValue { register: 0x0, value: 0x01 },
+            Instruction::Return,
+        ]
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let (mut emulator, mut debugger) = setup(subroutine_program());
+        debugger.add_breakpoint(0x204);
+        debugger.resume();
+        let (_, reason) = debugger.run_frame(&mut emulator, EmulatorInput::new());
+        assert_eq!(reason, Some(StopReason::Breakpoint(0x204)));
+        assert!(debugger.paused());
+        assert_eq!(emulator.state().register(0x0), 1);
+        assert_eq!(emulator.state().register(0x1), 1);
+
+        debugger.resume();
+        let (_, reason) = debugger.run_frame(&mut emulator, EmulatorInput::new());
+        assert_eq!(reason, Some(StopReason::Breakpoint(0x204)));
+        assert_eq!(emulator.state().register(0x1), 2);
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let (mut emulator, mut debugger) = setup(subroutine_program());
+        debugger.step_over(&mut emulator).unwrap();
+        let (_, reason) = debugger.run_frame(&mut emulator, EmulatorInput::new());
+        assert_eq!(reason, Some(StopReason::Step(0x202)));
+        assert_eq!(emulator.state().register(0x0), 1);
+        assert!(emulator.state().stack().is_empty());
+
+        let (mut emulator, mut debugger) = setup(subroutine_program());
+        debugger.step(&mut emulator).unwrap();
+        assert_eq!(emulator.state().program_counter, 0x206);
+        debugger.step_out(&emulator).unwrap();
+        let (_, reason) = debugger.run_frame(&mut emulator, EmulatorInput::new());
+        assert_eq!(reason, Some(StopReason::Step(0x202)));
+        assert!(debugger.step_out(&emulator).is_err());
+    }
+
+    #[test]
+    fn test_stack_trace() {
+        let (mut emulator, mut debugger) = setup(subroutine_program());
+        assert_eq!(stack(emulator.state()), "Stack is empty.");
+        debugger.step(&mut emulator).unwrap();
+        assert_eq!(stack(emulator.state()), "Stack depth 1 of 16:\n#0 0x0202 (called from 0x0200)");
+    }
+
+    #[test]
+    fn test_fault() {
+        let (mut emulator, mut debugger) = setup(vec![ Instruction::Return ]);
+        debugger.resume();
+        let (output, reason) = debugger.run_frame(&mut emulator, EmulatorInput::new());
+        assert_eq!(reason, output.fault.map(StopReason::Fault));
+        assert!(reason.is_some());
+        assert!(debugger.paused());
+        assert!(debugger.execute(&mut emulator, DebuggerCommand::Step).is_err());
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!("b 0x200".parse(), Ok(DebuggerCommand::Break { address: 0x200 }));
+        assert_eq!("x 512 4".parse(), Ok(DebuggerCommand::Memory { address: 0x200, length: 4 }));
+        assert_eq!("memory 0x200".parse(), Ok(DebuggerCommand::Memory { address: 0x200, length: DEFAULT_HEXDUMP_LENGTH }));
+        assert!("break".parse::<DebuggerCommand>().is_err());
+        assert!("jump 0x200".parse::<DebuggerCommand>().is_err());
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let (emulator, _) = setup(vec![Instruction::ClearScreen]);
+        assert_eq!(hexdump(emulator.state(), 0x200, 2), "0x0200: 00 E0");
+        assert_eq!(hexdump(emulator.state(), 0xFFF, 16), "0x0FFF: 00");
+    }
+}
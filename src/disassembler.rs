@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::instructions::Instruction;
+use crate::settings::Settings;
+
+const DATA_ROW_LENGTH: usize = 8;
+
+/// The kind of reference to an address, determining the prefix of its label.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Subroutine,
+    Location,
+    Data,
+}
+
+/// A program split into code and data by following the control flow from the program start address.
+pub struct Disassembly {
+    start_address: u16,
+    settings: Settings,
+    program: Vec<u8>,
+    /// Instructions by address, for all bytes reachable as code.
+    instructions: BTreeMap<u16, Instruction>,
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+impl Disassembly {
+    /// Analyzes a program loaded at the program start address of the settings, following jumps, calls and skips from
+    /// there. The settings also determine how quirky instructions are shown.
+    pub fn new(program: &[u8], settings: &Settings) -> Self {
+        let start_address = settings.program_start_address;
+        let mut disassembly = Self {
+            start_address,
+            settings: *settings,
+            program: program.to_vec(),
+            instructions: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+
+        let mut pending = vec![start_address];
+        let mut visited = BTreeSet::new();
+        while let Some(address) = pending.pop() {
+            if !visited.insert(address) {
+                continue;
+            }
+            let instruction = match disassembly.instruction(address) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            disassembly.instructions.insert(address, instruction);
+
+            let next = address.wrapping_add(instruction.size());
+            match instruction {
+                Instruction::Jump { address: target } => {
+                    disassembly.add_label(target, LabelKind::Location);
+                    pending.push(target);
+                },
+                Instruction::Call { address: target } => {
+                    disassembly.add_label(target, LabelKind::Subroutine);
+                    pending.push(target);
+                    pending.push(next);
+                },
+                Instruction::JumpWithOffset { address: target } => {
+                    // The offset is only known at runtime, so only the base address can be labelled.
+                    disassembly.add_label(target, LabelKind::Location);
+                },
+                Instruction::LoadAddress { address: target } | Instruction::LoadLongAddress { address: target } => {
+                    disassembly.add_label(target, LabelKind::Data);
+                    pending.push(next);
+                },
+                Instruction::SkipIfValue { .. } |
+                Instruction::SkipIfNotValue { .. } |
+                Instruction::SkipIfEqual { .. } |
+                Instruction::SkipIfNotEqual { .. } |
+                Instruction::SkipIfKeyDown { .. } |
+                Instruction::SkipIfKeyUp { .. } => {
+                    pending.push(next);
+                    if let Some(skipped) = disassembly.instruction(next) {
+                        pending.push(next.wrapping_add(skipped.size()));
+                    }
+                },
+                Instruction::Return | Instruction::Exit => {},
+                _ => pending.push(next),
+            }
+        }
+
+        disassembly.remove_overlaps();
+        disassembly
+    }
+
+    /// The instructions found by the analysis, by address.
+    pub fn instructions(&self) -> &BTreeMap<u16, Instruction> {
+        &self.instructions
+    }
+
+    /// The label of the given address, if it is referenced by the program.
+    pub fn label(&self, address: u16) -> Option<String> {
+        let prefix = match self.labels.get(&address)? {
+            LabelKind::Subroutine => "sub",
+            LabelKind::Location => "loc",
+            LabelKind::Data => "data",
+        };
+        Some(format!("{}_{:04X}", prefix, address))
+    }
+
+    /// Formats the instruction, referring to addresses within the program by label.
+    pub fn format_instruction(&self, instruction: Instruction) -> String {
+        let (mnemonic, address) = match instruction {
+            Instruction::Jump { address } => ("JP".to_string(), address),
+            Instruction::JumpWithOffset { address } => {
+                let register = if self.settings.use_flexible_jump_offset { address >> 8 } else { 0x0 };
+                (format!("JP V{:X},", register), address)
+            },
+            Instruction::Call { address } => ("CALL".to_string(), address),
+            Instruction::LoadAddress { address } => ("LD I,".to_string(), address),
+            // The assembler only uses the long form for addresses beyond 12 bits, so other ones are kept as data.
+            Instruction::LoadLongAddress { address } if address <= 0xFFF => return format!("DW 0xF000, 0x{:04X}", address),
+            Instruction::LoadLongAddress { address } => ("LD I,".to_string(), address),
+            _ => return instruction.format(&self.settings),
+        };
+        match self.label(address) {
+            Some(label) => format!("{} {}", mnemonic, label),
+            None => instruction.format(&self.settings),
+        }
+    }
+
+    /// A listing of the program as assembly source, with addresses and raw opcodes in comments.
+    pub fn listing(&self) -> String {
+        let mut lines = vec![];
+        let end_address = self.end_address();
+        let mut address = self.start_address as u32;
+        while address < end_address {
+            let address_u16 = address as u16;
+            if let Some(label) = self.label(address_u16) {
+                lines.push(format!("{}:", label));
+            }
+
+            if let Some(&instruction) = self.instructions.get(&address_u16) {
+                let bytes = self.bytes(address_u16, instruction.size());
+                let opcode: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                lines.push(format!("    {:<27} ; 0x{:04X}: {}", self.format_instruction(instruction), address, opcode));
+                address += instruction.size() as u32;
+                continue;
+            }
+
+            // Data runs end at the next instruction or label, so that both can be emitted in place.
+            let mut length = 1;
+            while length < DATA_ROW_LENGTH as u32 && address + length < end_address {
+                let next = (address + length) as u16;
+                if self.instructions.contains_key(&next) || self.labels.contains_key(&next) {
+                    break;
+                }
+                length += 1;
+            }
+            let bytes: Vec<String> = self.bytes(address_u16, length as u16).iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            lines.push(format!("    {:<27} ; 0x{:04X}", format!("DB {}", bytes.join(", ")), address));
+            address += length;
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// Drops instructions and labels that start within the bytes of a preceding instruction, as they cannot be listed.
+    fn remove_overlaps(&mut self) {
+        let mut inner_addresses = vec![];
+        let mut address = self.start_address as u32;
+        while address < self.end_address() {
+            let size = self.instructions.get(&(address as u16)).map_or(1, |instruction| instruction.size() as u32);
+            inner_addresses.extend((address + 1 .. address + size).map(|address| address as u16));
+            address += size;
+        }
+        for address in inner_addresses {
+            self.instructions.remove(&address);
+            self.labels.remove(&address);
+        }
+    }
+
+    fn end_address(&self) -> u32 {
+        self.start_address as u32 + self.program.len() as u32
+    }
+
+    fn contains(&self, address: u16) -> bool {
+        address >= self.start_address && (address as u32) < self.end_address()
+    }
+
+    fn bytes(&self, address: u16, length: u16) -> &[u8] {
+        let start = (address - self.start_address) as usize;
+        let end = (start + length as usize).min(self.program.len());
+        &self.program[start .. end]
+    }
+
+    fn add_label(&mut self, address: u16, kind: LabelKind) {
+        if self.contains(address) {
+            let label = self.labels.entry(address).or_insert(kind);
+            *label = (*label).min(kind);
+        }
+    }
+
+    /// Decodes the instruction at the given address, if it lies entirely within the program.
+    fn instruction(&self, address: u16) -> Option<Instruction> {
+        let word = |address: u16| -> Option<u16> {
+            if !self.contains(address) || !self.contains(address.wrapping_add(1)) {
+                return None;
+            }
+            let bytes = self.bytes(address, 2);
+            Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+        };
+        let opcode = word(address)?;
+        if Instruction::is_long(opcode) {
+            Some(Instruction::decode_long(opcode, word(address.wrapping_add(2))?))
+        } else {
+            Some(Instruction::decode(opcode))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(instructions: &[Instruction], data: &[u8]) -> Vec<u8> {
+        let mut program = vec![];
+        for instruction in instructions {
+            program.extend(instruction.encode_bytes());
+        }
+        program.extend_from_slice(data);
+        program
+    }
+
+    #[test]
+    fn test_separates_code_and_data() {
+        let program = assemble(&[
+            Instruction::LoadAddress { address: 0x208 },
+            Instruction::Call { address: 0x206 },
+            Instruction::Jump { address: 0x204 },
+            Instruction::Return,
+        ], &[0xFF, 0x81]);
+        let disassembly = Disassembly::new(&program, &Settings::default());
+        assert_eq!(disassembly.instructions().len(), 4);
+        assert!(!disassembly.instructions().contains_key(&0x208));
+        assert_eq!(disassembly.label(0x204).as_deref(), Some("loc_0204"));
+        assert_eq!(disassembly.label(0x206).as_deref(), Some("sub_0206"));
+        assert_eq!(disassembly.label(0x208).as_deref(), Some("data_0208"));
+        assert_eq!(disassembly.format_instruction(Instruction::Call { address: 0x206 }), "CALL sub_0206");
+
+        let listing = disassembly.listing();
+        assert!(listing.contains("    CALL sub_0206               ; 0x0202: 2206\n"));
+        assert!(listing.contains("data_0208:\n    DB 0xFF, 0x81"));
+    }
+
+    #[test]
+    fn test_follows_skips_over_long_instructions() {
+        let program = assemble(&[
+            Instruction::SkipIfValue { register: 0x0, comparand_value: 0x00 },
+            Instruction::LoadLongAddress { address: 0x1234 },
+            Instruction::Exit,
+        ], &[]);
+        let disassembly = Disassembly::new(&program, &Settings::default());
+        assert_eq!(disassembly.instructions().keys().copied().collect::<Vec<u16>>(), vec![0x200, 0x202, 0x206]);
+    }
+
+    #[test]
+    fn test_formats_quirky_instructions() {
+        let program = assemble(&[Instruction::JumpWithOffset { address: 0x202 }, Instruction::Exit], &[]);
+        let settings = Settings { use_flexible_jump_offset: true, ..Settings::default() };
+        let disassembly = Disassembly::new(&program, &settings);
+        assert_eq!(disassembly.format_instruction(Instruction::JumpWithOffset { address: 0x202 }), "JP V2, loc_0202");
+        assert_eq!(disassembly.format_instruction(Instruction::JumpWithOffset { address: 0x310 }), "JP V3, 0x310");
+        // The assembler would use the short form for this address.
+        assert_eq!(disassembly.format_instruction(Instruction::LoadLongAddress { address: 0x300 }), "DW 0xF000, 0x0300");
+    }
+}
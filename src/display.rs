@@ -1,84 +1,449 @@
+use crate::savestate::{Reader, SaveStateError, Writer};
+
+const PLANE_COUNT: u8 = 2;
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// A set of display rows, such as the rows changed since the last frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirtyRows([u64; 4]);
+
+impl DirtyRows {
+    /// The set of all rows of a display of the given height.
+    pub fn all(height: u8) -> Self {
+        let mut rows = Self::default();
+        for row in 0..height {
+            rows.insert(row);
+        }
+        rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&bits| bits == 0)
+    }
+
+    pub fn contains(&self, row: u8) -> bool {
+        self.0[row as usize / 64] & 1 << (row % 64) != 0
+    }
+
+    /// The rows in the set, from the top.
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=u8::MAX).filter(|&row| self.contains(row))
+    }
+
+    pub fn insert(&mut self, row: u8) {
+        self.0[row as usize / 64] |= 1 << (row % 64);
+    }
+
+    /// Adds the rows of the other set.
+    pub fn extend(&mut self, other: DirtyRows) {
+        for (bits, other_bits) in self.0.iter_mut().zip(other.0) {
+            *bits |= other_bits;
+        }
+    }
+}
+
+/// The display, stored as one bitboard per plane. Each row is packed into 64-bit words with the leftmost pixel in the
+/// most significant bit, so that sprite rows are drawn by XORing shifted masks, and collisions are found with an AND.
 #[derive(Clone)]
 pub struct Display {
     pub width: u8,
     pub height: u8,
+    low_resolution_width: u8,
+    low_resolution_height: u8,
+    high_resolution: bool,
     wrap_sprites: bool,
-    framebuffer: Vec<bool>,
+    selected_planes: u8,
+    /// The number of words per row.
+    row_words: usize,
+    /// The rows of each plane, one after another. Bits beyond the display width are always clear.
+    planes: [Vec<u64>; PLANE_COUNT as usize],
+    /// The rows changed since they were last taken.
+    dirty_rows: DirtyRows,
 }
 
 impl Display {
     pub fn new(width: u8, height: u8, wrap_sprites: bool) -> Display {
+        let row_words = Self::row_words(width);
         Display {
-            width: width,
-            height: height,
-            wrap_sprites: wrap_sprites,
-            framebuffer: vec![false; width as usize * height as usize],
+            width,
+            height,
+            low_resolution_width: width,
+            low_resolution_height: height,
+            high_resolution: false,
+            wrap_sprites,
+            selected_planes: 0x1,
+            row_words,
+            planes: Self::blank_planes(row_words, height),
+            dirty_rows: DirtyRows::all(height),
         }
     }
 
+    pub fn high_resolution(&self) -> bool {
+        self.high_resolution
+    }
+
+    /// Switches between low resolution and high resolution (double width and height) mode, clearing the display.
+    pub fn set_high_resolution(&mut self, high_resolution: bool) {
+        let scale = if high_resolution { 2 } else { 1 };
+        self.high_resolution = high_resolution;
+        self.width = self.low_resolution_width * scale;
+        self.height = self.low_resolution_height * scale;
+        self.row_words = Self::row_words(self.width);
+        self.planes = Self::blank_planes(self.row_words, self.height);
+        self.dirty_rows = DirtyRows::all(self.height);
+    }
+
+    /// The bitmask of planes affected by drawing, clearing and scrolling (XO-CHIP).
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.selected_planes = planes & ((1 << PLANE_COUNT) - 1);
+    }
+
+    pub fn selected_plane_count(&self) -> u8 {
+        self.selected_planes.count_ones() as u8
+    }
+
     pub fn clear(&mut self) {
-        for x in 0..self.width {
-            for y in 0..self.height {
-                self.set_visibility(x, y, false);
-            }
+        for plane in self.selected_plane_indices() {
+            self.planes[plane].fill(0);
         }
+        self.mark_all_rows_dirty();
+    }
+
+    /// Yields the rows changed since the last call, such as by drawing, scrolling or clearing, and starts over.
+    pub fn take_dirty_rows(&mut self) -> DirtyRows {
+        std::mem::take(&mut self.dirty_rows)
     }
 
+    /// Applies a sprite 8 pixels wide. If multiple planes are selected, the sprite data is split evenly between them.
     pub fn apply_sprite(&mut self, x_start: u8, y_start: u8, sprite: &[u8]) -> bool {
-        let (x_start, y_start) = self.wrap(x_start, y_start);
         let mut pixels_hidden = false;
-        for (dy, mask) in sprite.iter().enumerate() {
-            let y = y_start + (dy as u8);
-            for dx in 0..8 {
-                let x = x_start + dx;
-                let was_displayed = self.is_visible(x, y);
-                let is_displayed = (mask & (0x80 >> dx) > 0) ^ was_displayed;
-                self.set_visibility(x, y, is_displayed);
-                if !is_displayed && was_displayed {
-                    pixels_hidden = true;
-                }
-            }
+        for (plane, plane_sprite) in self.split_sprite(sprite) {
+            let rows = plane_sprite.iter().map(|&mask| (mask as u16) << 8);
+            pixels_hidden |= self.apply_sprite_rows(x_start, y_start, plane, rows);
+        }
+        pixels_hidden
+    }
+
+    /// Applies a 16x16 sprite, where each row consists of two bytes.
+    pub fn apply_large_sprite(&mut self, x_start: u8, y_start: u8, sprite: &[u8]) -> bool {
+        let mut pixels_hidden = false;
+        for (plane, plane_sprite) in self.split_sprite(sprite) {
+            let rows = plane_sprite.chunks(2).map(|row| (row[0] as u16) << 8 | row[1] as u16);
+            pixels_hidden |= self.apply_sprite_rows(x_start, y_start, plane, rows);
         }
         pixels_hidden
     }
-    
+
+    pub fn scroll_down(&mut self, rows: u8) {
+        let shift = (rows as usize).min(self.height as usize) * self.row_words;
+        for plane in self.selected_plane_indices() {
+            let words = &mut self.planes[plane];
+            let length = words.len();
+            words.copy_within(.. length - shift, shift);
+            words[.. shift].fill(0);
+        }
+        self.mark_all_rows_dirty();
+    }
+
+    pub fn scroll_up(&mut self, rows: u8) {
+        let shift = (rows as usize).min(self.height as usize) * self.row_words;
+        for plane in self.selected_plane_indices() {
+            let words = &mut self.planes[plane];
+            let length = words.len();
+            words.copy_within(shift .., 0);
+            words[length - shift ..].fill(0);
+        }
+        self.mark_all_rows_dirty();
+    }
+
+    pub fn scroll_left(&mut self, columns: u8) {
+        for plane in self.selected_plane_indices() {
+            for row in self.planes[plane].chunks_mut(self.row_words) {
+                shift_row_left(row, columns as usize);
+            }
+        }
+        self.mark_all_rows_dirty();
+    }
+
+    pub fn scroll_right(&mut self, columns: u8) {
+        let last_word_mask = self.last_word_mask();
+        for plane in self.selected_plane_indices() {
+            for row in self.planes[plane].chunks_mut(self.row_words) {
+                shift_row_right(row, columns as usize);
+                row[row.len() - 1] &= last_word_mask;
+            }
+        }
+        self.mark_all_rows_dirty();
+    }
+
+    /// The bitmask of planes that are set at the given position.
+    pub fn pixel(&self, x: u8, y: u8) -> u8 {
+        if x >= self.width || y >= self.height {
+            return 0x0;
+        }
+
+        let (index, bit) = self.position(x as usize, y as usize);
+        (0..PLANE_COUNT)
+            .filter(|&plane| self.planes[plane as usize][index] & bit != 0)
+            .fold(0x0, |pixel, plane| pixel | 1 << plane)
+    }
+
     pub fn visible_pixels(&self) -> Vec<(u8, u8)> {
         let mut visible_pixels = vec![];
-        for x in 0..self.width {
-            for y in 0..self.height {
-                if self.is_visible(x, y) {
-                    visible_pixels.push((x, y));
+        for y in 0..self.height as usize {
+            for word in 0..self.row_words {
+                let index = y * self.row_words + word;
+                let mut bits = self.planes.iter().fold(0, |bits, plane| bits | plane[index]);
+                while bits != 0 {
+                    let x = word * WORD_BITS + bits.leading_zeros() as usize;
+                    visible_pixels.push((x as u8, y as u8));
+                    bits &= !(1 << (WORD_BITS - 1 - x % WORD_BITS));
                 }
             }
         }
         visible_pixels
     }
 
-    fn is_visible(&self, x: u8, y: u8) -> bool {
-        let (x, y) = if self.wrap_sprites { self.wrap(x, y) } else { (x, y) };
+    pub(crate) fn save_to(&self, writer: &mut Writer) {
+        writer.u8(self.width);
+        writer.u8(self.height);
+        writer.u8(self.low_resolution_width);
+        writer.u8(self.low_resolution_height);
+        writer.bool(self.high_resolution);
+        writer.bool(self.wrap_sprites);
+        writer.u8(self.selected_planes);
+        // The framebuffer is saved as one byte of plane bits per pixel, which does not depend on the packing.
+        let framebuffer: Vec<u8> = (0..self.height).flat_map(|y| (0..self.width).map(move |x| self.pixel(x, y))).collect();
+        writer.vec(&framebuffer);
+    }
+
+    pub(crate) fn load_from(reader: &mut Reader) -> Result<Self, SaveStateError> {
+        let width = reader.u8()?;
+        let height = reader.u8()?;
+        let row_words = Self::row_words(width);
+        let mut display = Display {
+            width,
+            height,
+            low_resolution_width: reader.u8()?,
+            low_resolution_height: reader.u8()?,
+            high_resolution: reader.bool()?,
+            wrap_sprites: reader.bool()?,
+            selected_planes: reader.u8()?,
+            row_words,
+            planes: Self::blank_planes(row_words, height),
+            dirty_rows: DirtyRows::all(height),
+        };
+        let framebuffer = reader.vec()?;
+        let scale = if display.high_resolution { 2 } else { 1 };
+        let low_resolution_size = (display.low_resolution_width, display.low_resolution_height);
+        if width == 0 || height == 0 || low_resolution_size.0 >= 128 || low_resolution_size.1 >= 128
+            || (width, height) != (low_resolution_size.0 * scale, low_resolution_size.1 * scale)
+            || framebuffer.len() != width as usize * height as usize {
+            return Err(SaveStateError::InvalidValue("display"));
+        }
+        for (i, &pixel) in framebuffer.iter().enumerate() {
+            if pixel >> PLANE_COUNT != 0 {
+                return Err(SaveStateError::InvalidValue("display"));
+            }
+            let (index, bit) = display.position(i % width as usize, i / width as usize);
+            for (plane, words) in display.planes.iter_mut().enumerate() {
+                if pixel & 1 << plane != 0 {
+                    words[index] |= bit;
+                }
+            }
+        }
+        Ok(display)
+    }
+
+    fn split_sprite<'a>(&self, sprite: &'a [u8]) -> Vec<(u8, &'a [u8])> {
+        let plane_count = self.selected_plane_count() as usize;
+        if plane_count == 0 {
+            return vec![];
+        }
+
+        let chunk_size = (sprite.len() / plane_count).max(1);
+        let planes = (0..PLANE_COUNT).filter(|plane| self.selected_planes & (1 << plane) > 0);
+        planes.zip(sprite.chunks(chunk_size)).collect()
+    }
+
+    /// XORs rows of up to 16 pixels, given from the most significant bit, onto the plane, clipping or wrapping them at
+    /// the display edges. Yields whether any pixels were hidden.
+    fn apply_sprite_rows<I>(&mut self, x_start: u8, y_start: u8, plane: u8, rows: I) -> bool
+    where
+        I: Iterator<Item = u16>,
+    {
+        let (x_start, y_start) = self.wrap(x_start, y_start);
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut pixels_hidden = false;
+        for (dy, row) in rows.enumerate() {
+            let y = y_start as usize + dy;
+            let y = match y < height {
+                true => y,
+                false if self.wrap_sprites => y % height,
+                false => break,
+            };
 
-        if x < self.width && y < self.height {
-            let i = self.index(x, y);
-            return self.framebuffer[i];
+            let mut mask = (row as u64) << (WORD_BITS - 16);
+            let mut x = x_start as usize;
+            while mask != 0 {
+                // Only the pixels up to the right edge fit, and the rest are wrapped around to the left edge, if at all.
+                let fit = width - x;
+                let clipped_mask = if fit < WORD_BITS { mask & !(u64::MAX >> fit) } else { mask };
+                pixels_hidden |= self.xor_row(plane as usize, y, x, clipped_mask);
+                if !self.wrap_sprites || fit >= WORD_BITS {
+                    break;
+                }
+                mask <<= fit;
+                x = 0;
+            }
         }
+        pixels_hidden
+    }
 
-        false
+    /// XORs a mask of pixels, given from the most significant bit, onto a row from the given column. Yields whether
+    /// any pixels were hidden.
+    fn xor_row(&mut self, plane: usize, y: usize, x: usize, mask: u64) -> bool {
+        let start = y * self.row_words;
+        let row = &mut self.planes[plane][start .. start + self.row_words];
+        let (word, shift) = (x / WORD_BITS, x % WORD_BITS);
+        if mask != 0 {
+            self.dirty_rows.insert(y as u8);
+        }
+        let mut pixels_hidden = false;
+        let mut xor = |index: usize, bits: u64| {
+            pixels_hidden |= row[index] & bits != 0;
+            row[index] ^= bits;
+        };
+        xor(word, mask >> shift);
+        if shift > 0 && word + 1 < self.row_words {
+            xor(word + 1, mask << (WORD_BITS - shift));
+        }
+        pixels_hidden
     }
 
-    fn set_visibility(&mut self, x: u8, y: u8, visible: bool) {
-        let (x, y) = if self.wrap_sprites { self.wrap(x, y) } else { (x, y) };
+    /// The index of the word holding the given position in a plane, along with the bit of the position in the word.
+    fn position(&self, x: usize, y: usize) -> (usize, u64) {
+        (y * self.row_words + x / WORD_BITS, 1 << (WORD_BITS - 1 - x % WORD_BITS))
+    }
 
-        if x < self.width && y < self.height {
-            let i = self.index(x, y);
-            self.framebuffer[i] = visible;
+    /// The bits of the last word of a row that are within the display width.
+    fn last_word_mask(&self) -> u64 {
+        match self.width as usize % WORD_BITS {
+            0 => u64::MAX,
+            bits => !(u64::MAX >> bits),
         }
     }
 
-    fn index(&self, x: u8, y: u8) -> usize {
-        (x as usize) + (y as usize) * (self.width as usize)
+    fn mark_all_rows_dirty(&mut self) {
+        if self.selected_planes != 0 {
+            self.dirty_rows = DirtyRows::all(self.height);
+        }
     }
-    
+
+    fn selected_plane_indices(&self) -> impl Iterator<Item = usize> {
+        let selected_planes = self.selected_planes;
+        (0..PLANE_COUNT as usize).filter(move |plane| selected_planes & (1 << plane) > 0)
+    }
+
+    fn row_words(width: u8) -> usize {
+        (width as usize).div_ceil(WORD_BITS)
+    }
+
+    fn blank_planes(row_words: usize, height: u8) -> [Vec<u64>; PLANE_COUNT as usize] {
+        std::array::from_fn(|_| vec![0; row_words * height as usize])
+    }
+
     fn wrap(&self, x: u8, y: u8) -> (u8, u8) {
         (x % self.width, y % self.height)
     }
-}
\ No newline at end of file
+}
+
+/// Shifts the pixels of a packed row to the left, filling in blank pixels on the right.
+fn shift_row_left(row: &mut [u64], columns: usize) {
+    let (words, shift) = (columns / WORD_BITS, columns % WORD_BITS);
+    for index in 0..row.len() {
+        let high = row.get(index + words).copied().unwrap_or(0);
+        let low = row.get(index + words + 1).copied().unwrap_or(0);
+        row[index] = if shift == 0 { high } else { high << shift | low >> (WORD_BITS - shift) };
+    }
+}
+
+/// Shifts the pixels of a packed row to the right, filling in blank pixels on the left.
+fn shift_row_right(row: &mut [u64], columns: usize) {
+    let (words, shift) = (columns / WORD_BITS, columns % WORD_BITS);
+    for index in (0..row.len()).rev() {
+        let low = index.checked_sub(words).map_or(0, |source| row[source]);
+        let high = index.checked_sub(words + 1).map_or(0, |source| row[source]);
+        row[index] = if shift == 0 { low } else { low >> shift | high << (WORD_BITS - shift) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collision() {
+        let mut display = Display::new(64, 32, false);
+        assert!(!display.apply_sprite(60, 0, &[0xF0]));
+        assert!(display.apply_sprite(62, 0, &[0xC0]));
+        assert_eq!(display.visible_pixels(), vec![(60, 0), (61, 0)]);
+    }
+
+    #[test]
+    fn test_clip_and_wrap() {
+        let mut display = Display::new(64, 32, false);
+        display.apply_large_sprite(60, 31, &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(display.visible_pixels(), vec![(60, 31), (61, 31), (62, 31), (63, 31)]);
+
+        let mut display = Display::new(64, 32, true);
+        display.apply_sprite(62, 31, &[0xE0, 0x80]);
+        assert_eq!(display.visible_pixels(), vec![(62, 0), (0, 31), (62, 31), (63, 31)]);
+    }
+
+    #[test]
+    fn test_wide_display() {
+        let mut display = Display::new(64, 32, false);
+        display.set_high_resolution(true);
+        display.apply_large_sprite(60, 0, &[0x80, 0x01]);
+        assert_eq!(display.visible_pixels(), vec![(60, 0), (75, 0)]);
+        display.scroll_right(4);
+        assert_eq!(display.visible_pixels(), vec![(64, 0), (79, 0)]);
+        display.scroll_left(70);
+        assert_eq!(display.visible_pixels(), vec![(9, 0)]);
+        display.scroll_right(118);
+        assert_eq!(display.visible_pixels(), vec![(127, 0)]);
+        display.scroll_right(1);
+        assert!(display.visible_pixels().is_empty());
+    }
+
+    #[test]
+    fn test_scroll_selected_planes() {
+        let mut display = Display::new(64, 32, false);
+        display.select_planes(0x3);
+        display.apply_sprite(0, 0, &[0x80, 0x80]);
+        display.select_planes(0x2);
+        display.scroll_down(2);
+        assert_eq!(display.pixel(0, 0), 0x1);
+        assert_eq!(display.pixel(0, 2), 0x2);
+        display.scroll_up(40);
+        display.select_planes(0x1);
+        display.clear();
+        assert!(display.visible_pixels().is_empty());
+    }
+
+    #[test]
+    fn test_dirty_rows() {
+        let mut display = Display::new(64, 32, true);
+        assert_eq!(display.take_dirty_rows(), DirtyRows::all(32));
+        assert!(display.take_dirty_rows().is_empty());
+        display.apply_sprite(0, 30, &[0x80, 0x00, 0x80]);
+        assert_eq!(display.take_dirty_rows().iter().collect::<Vec<u8>>(), vec![0, 30]);
+        display.scroll_left(1);
+        assert_eq!(display.take_dirty_rows(), DirtyRows::all(32));
+    }
+}
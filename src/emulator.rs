@@ -1,9 +1,12 @@
 use std::time::{Duration, Instant};
-use crate::cpu::{Cpu, CpuCycleResult};
-use crate::display::Display;
+use crate::audio::AudioPattern;
+use crate::cpu::{Cpu, CpuCycleResult, CpuError};
+use crate::display::{DirtyRows, Display};
 use crate::keypad::Keypad;
+use crate::rewind::RewindBuffer;
+use crate::savestate::SaveStateError;
 use crate::settings::Settings;
-use crate::state::State;
+use crate::state::{State, StateError};
 
 #[derive(Clone)]
 pub struct EmulatorInput {
@@ -24,64 +27,227 @@ impl EmulatorInput {
     }
 }
 
+impl Default for EmulatorInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The outcome of a frame. The display itself is borrowed from the emulator with `Emulator::display`, rather than
+/// copied for every frame.
 pub struct EmulatorOutput {
-    pub display: Display,
+    /// Changes whenever the display changes, or is replaced by rewinding or loading a state.
+    pub display_version: u64,
+    /// The display rows changed since the display was at `dirty_since_version`, which is its version at the start of
+    /// the frame. Frontends that drew an older version must redraw all rows instead.
+    pub dirty_rows: DirtyRows,
+    pub dirty_since_version: u64,
     pub sound_playing: bool,
+    pub audio_pattern: Option<AudioPattern>,
+    pub halted: bool,
+    /// The fault that halted the program, if any.
+    pub fault: Option<CpuError>,
 }
 
 pub struct Emulator {
     settings: Settings,
     state: State,
     cpu: Cpu,
+    fault: Option<CpuError>,
+    display_version: u64,
+    dirty_rows: DirtyRows,
+    dirty_since_version: u64,
+    /// The states at the start of recent frames.
+    rewind_buffer: RewindBuffer,
 }
 
 impl Emulator {
-    pub fn new(settings: Settings, program: Vec<u8>) -> Self {
-        Self {
-            settings: settings,
-            state: State::new(settings, program),
+    /// Creates an emulator running the program, or fails if the settings are not supported or the program does not fit
+    /// in memory.
+    pub fn new(settings: Settings, program: Vec<u8>) -> Result<Self, StateError> {
+        // Frames are timed by dividing by the frame rate, and a clock without cycles would never run the program.
+        if settings.frame_rate == 0 {
+            return Err(StateError::InvalidSettings("frame rate"));
+        }
+        if settings.clock_speed == 0 {
+            return Err(StateError::InvalidSettings("clock speed"));
+        }
+        Ok(Self {
+            settings,
+            state: State::new(settings, program)?,
             cpu: Cpu::new(settings),
+            fault: None,
+            display_version: 0,
+            dirty_rows: DirtyRows::default(),
+            dirty_since_version: 0,
+            rewind_buffer: RewindBuffer::new(),
+        })
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.state.display
+    }
+
+    /// Replaces the machine state with one serialized by `State::save`, adopting the settings it was saved with.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let state = State::load(data)?;
+        self.settings = state.settings();
+        self.cpu = Cpu::new(self.settings);
+        self.state = state;
+        self.fault = None;
+        self.rewind_buffer.clear();
+        self.replace_display();
+        Ok(())
+    }
+
+    /// Sets the number of frames that can be rewound. A capacity of 0 disables rewinding.
+    pub fn set_rewind_capacity(&mut self, frames: usize) {
+        self.rewind_buffer.set_capacity(frames);
+    }
+
+    /// The number of frames that can currently be rewound.
+    pub fn rewind_frames(&self) -> usize {
+        self.rewind_buffer.len()
+    }
+
+    /// Restores the state from the start of the previous frame, or yields `None` if there are no frames left to rewind.
+    pub fn rewind(&mut self) -> Option<EmulatorOutput> {
+        self.state = self.rewind_buffer.pop()?;
+        self.fault = None;
+        self.replace_display();
+        Some(self.output())
+    }
+
+    /// The fault that halted the program, if any. Loading a state or rewinding clears it.
+    pub fn fault(&self) -> Option<CpuError> {
+        self.fault
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.settings.frame_rate as f64)
+    }
+
+    pub fn cycles_per_frame(&self) -> u32 {
+        (self.settings.clock_speed as f64 / self.settings.frame_rate as f64) as u32
+    }
+
+    pub fn output(&self) -> EmulatorOutput {
+        EmulatorOutput {
+            display_version: self.display_version,
+            dirty_rows: self.dirty_rows,
+            dirty_since_version: self.dirty_since_version,
+            sound_playing: self.state.sound_playing(),
+            audio_pattern: self.state.audio_pattern(),
+            halted: self.state.program_terminated() || self.fault.is_some(),
+            fault: self.fault,
         }
     }
 
+    /// Marks the whole display as changed, after the state was replaced.
+    fn replace_display(&mut self) {
+        self.state.display.take_dirty_rows();
+        self.display_version += 1;
+        self.dirty_rows = DirtyRows::all(self.state.display.height);
+    }
+
+    /// Executes a single CPU cycle, without updating timers or input. Once a fault occurs, it is returned without executing anything.
+    pub fn step_cycle(&mut self) -> Result<CpuCycleResult, CpuError> {
+        if let Some(fault) = self.fault {
+            return Err(fault);
+        }
+        let result = self.cpu.cycle(&mut self.state);
+        self.fault = result.err();
+        let dirty_rows = self.state.display.take_dirty_rows();
+        if !dirty_rows.is_empty() {
+            self.display_version += 1;
+            self.dirty_rows.extend(dirty_rows);
+        }
+        result
+    }
+
+    /// Applies the input and executes a single frame worth of CPU cycles, without any wall-clock pacing.
+    pub fn run_frame(&mut self, input: EmulatorInput) -> EmulatorOutput {
+        self.run_frame_until(input, |_| false).0
+    }
+
+    /// Runs a frame like `run_frame`, but checks the condition before every cycle and ends the frame early if it holds.
+    /// Also yields whether the frame was ended by the condition.
+    pub fn run_frame_until<F>(&mut self, input: EmulatorInput, mut condition: F) -> (EmulatorOutput, bool)
+    where
+        F: FnMut(&State) -> bool,
+    {
+        self.rewind_buffer.push(&self.state);
+
+        self.state.keypad = input.keypad;
+        self.dirty_rows = DirtyRows::default();
+        self.dirty_since_version = self.display_version;
+
+        self.state.decrement_delay_register();
+        self.state.decrement_sound_register();
+
+        let mut stopped = false;
+        for _ in 0..self.cycles_per_frame() {
+            if condition(&self.state) {
+                stopped = true;
+                break;
+            }
+            match self.step_cycle() {
+                Ok(CpuCycleResult::Wait | CpuCycleResult::Done) | Err(_) => {
+                    break;
+                },
+                Ok(CpuCycleResult::Continue) => {}
+            }
+        }
+
+        (self.output(), stopped)
+    }
+
+    /// Executes up to the given number of frames, keeping the current keypad state, and stops early if the program halts.
+    pub fn run_frames(&mut self, frames: u32) -> EmulatorOutput {
+        let mut output = self.output();
+        for _ in 0..frames {
+            if output.halted {
+                break;
+            }
+            let input = EmulatorInput { quit: false, keypad: self.state.keypad.clone() };
+            output = self.run_frame(input);
+        }
+        output
+    }
+
+    /// Runs frames paced by the frame rate until the program halts or the input asks to quit, passing the display
+    /// along with the output of each frame to the render function.
     pub fn execute<F>(&mut self, mut render: F)
     where
-        F: FnMut(EmulatorOutput) -> EmulatorInput,
+        F: FnMut(&Display, EmulatorOutput) -> EmulatorInput,
     {
-        let cycle_duration = Duration::from_secs_f64(1.0 / self.settings.clock_speed as f64);
-        let frame_duration = Duration::from_secs_f64(1.0 / self.settings.frame_rate as f64);
-        let cycles_per_frame = (frame_duration.as_secs_f64() / cycle_duration.as_secs_f64()) as i64;
+        let frame_duration = self.frame_duration();
 
+        let mut output = self.output();
         loop {
             let frame_clock = Instant::now();
 
-            let output = EmulatorOutput { 
-                display: self.state.display.clone(),
-                sound_playing: self.state.sound_playing(),
-            };
-            let input = render(output);
-            self.state.keypad = input.keypad;
-
+            let input = render(&self.state.display, output);
             if input.quit {
                 break;
             }
 
-            self.state.decrement_delay_register();
-            self.state.decrement_sound_register();
-
-            for _ in 0..cycles_per_frame {
-                let cycle_result = self.cpu.cycle(&mut self.state);
-
-                match cycle_result {
-                    CpuCycleResult::Wait => {
-                        break;
-                    },
-                    CpuCycleResult::Done => {
-                        return;
-                    },
-                    _ => {}
-                }
-            };
+            output = self.run_frame(input);
+            if output.halted {
+                return;
+            }
 
             let frame_elapsed_duration = frame_clock.elapsed();
             if frame_elapsed_duration < frame_duration {
@@ -89,4 +255,134 @@ impl Emulator {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::Instruction;
+
+    fn setup(instructions: Vec<Instruction>) -> Emulator {
+        let mut program = vec![];
+        for instruction in instructions {
+            program.extend(instruction.encode_bytes());
+        }
+        Emulator::new(Settings::default(), program).unwrap()
+    }
+
+    #[test]
+    fn test_run_frame_executes_cycles_per_frame() {
+        let program = vec![
+            Instruction::AddValue { register: 0x0, value: 0x01 },
+            Instruction::Jump { address: 0x200 },
+        ];
+        let mut emulator = setup(program);
+        let cycles_per_frame = emulator.cycles_per_frame();
+        let _ = emulator.run_frame(EmulatorInput::new());
+        assert_eq!(emulator.state().register(0x0) as u32, cycles_per_frame / 2);
+        let _ = emulator.run_frames(3);
+        assert_eq!(emulator.state().register(0x0) as u32, 4 * cycles_per_frame / 2);
+    }
+
+    #[test]
+    fn test_new_with_invalid_timing() {
+        let settings = Settings { frame_rate: 0, ..Settings::default() };
+        assert_eq!(Emulator::new(settings, vec![]).err(), Some(StateError::InvalidSettings("frame rate")));
+        let settings = Settings { clock_speed: 0, ..Settings::default() };
+        assert_eq!(Emulator::new(settings, vec![]).err(), Some(StateError::InvalidSettings("clock speed")));
+    }
+
+    #[test]
+    fn test_run_frames_stops_when_halted() {
+        let program = vec![ Instruction::Jump { address: 0xFFE } ];
+        let mut emulator = setup(program);
+        let output = emulator.run_frames(10);
+        assert!(output.halted);
+    }
+
+    #[test]
+    fn test_load_state() {
+        let program = vec![
+            Instruction::AddValue { register: 0x0, value: 0x01 },
+            Instruction::Jump { address: 0x200 },
+        ];
+        let mut emulator = setup(program);
+        let _ = emulator.run_frame(EmulatorInput::new());
+        let saved = emulator.state().save();
+        let register = emulator.state().register(0x0);
+
+        let _ = emulator.run_frames(2);
+        assert_ne!(emulator.state().register(0x0), register);
+        emulator.load_state(&saved).unwrap();
+        assert_eq!(emulator.state().register(0x0), register);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let program = vec![
+            Instruction::AddValue { register: 0x0, value: 0x01 },
+            Instruction::Jump { address: 0x200 },
+        ];
+        let mut emulator = setup(program);
+        emulator.set_rewind_capacity(2);
+        assert!(emulator.rewind().is_none());
+
+        let registers: Vec<u8> = (0..3).map(|_| {
+            let register = emulator.state().register(0x0);
+            let _ = emulator.run_frame(EmulatorInput::new());
+            register
+        }).collect();
+        assert_eq!(emulator.rewind_frames(), 2);
+
+        assert!(emulator.rewind().is_some());
+        assert_eq!(emulator.state().register(0x0), registers[2]);
+        assert!(emulator.rewind().is_some());
+        assert_eq!(emulator.state().register(0x0), registers[1]);
+        assert!(emulator.rewind().is_none());
+    }
+
+    #[test]
+    fn test_display_version() {
+        let program = vec![
+            Instruction::SkipIfKeyUp { register: 0x0 },
+            Instruction::DrawSprite { register_x: 0x0, register_y: 0x0, length: 1 },
+            Instruction::Jump { address: 0x200 },
+        ];
+        let mut emulator = setup(program);
+        emulator.set_rewind_capacity(1);
+        let version = emulator.run_frame(EmulatorInput::new()).display_version;
+        let output = emulator.run_frame(EmulatorInput::new());
+        assert_eq!(output.display_version, version);
+        assert!(output.dirty_rows.is_empty());
+
+        let mut input = EmulatorInput::new();
+        input.keypad.set_key_pressed(0x0);
+        let output = emulator.run_frame(input);
+        assert!(output.display_version > version);
+        assert_eq!(output.dirty_since_version, version);
+        assert_eq!(output.dirty_rows.iter().collect::<Vec<u8>>(), vec![0]);
+
+        let rewound = emulator.rewind().unwrap();
+        assert!(rewound.display_version > output.display_version);
+        assert_eq!(rewound.dirty_rows, DirtyRows::all(emulator.display().height));
+    }
+
+    #[test]
+    fn test_fault_halts_program() {
+        let program = vec![
+            Instruction::AddValue { register: 0x0, value: 0x01 },
+            Instruction::Return,
+        ];
+        let mut emulator = setup(program);
+        emulator.set_rewind_capacity(1);
+        let output = emulator.run_frame(EmulatorInput::new());
+        assert!(output.halted);
+        assert_eq!(output.fault, Some(CpuError::StackUnderflow { program_counter: 0x202, instruction: Instruction::Return }));
+        assert_eq!(emulator.step_cycle(), Err(output.fault.unwrap()));
+        assert_eq!(emulator.state().register(0x0), 0x01);
+
+        assert!(emulator.rewind().is_some());
+        assert_eq!(emulator.fault(), None);
+        assert!(!emulator.output().halted);
+    }
+}
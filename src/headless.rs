@@ -0,0 +1,225 @@
+use chip8::cpu::CpuError;
+use chip8::debugger;
+use chip8::{Display, Emulator, EmulatorInput};
+use crate::cli::HeadlessOptions;
+use crate::config::Config;
+use crate::movie_session::MovieSession;
+use crate::recording::Recorder;
+use crate::screenshot::Image;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+const HEXDUMP_CHUNK_SIZE: usize = 0x8000;
+
+/// How a headless run ended.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The program exited or ran past the end of memory.
+    Exited,
+    /// The frame limit was reached with the program still running.
+    FrameLimit,
+    /// The program was halted by a fault.
+    Fault(CpuError),
+}
+
+impl Outcome {
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            Outcome::Exited => 0,
+            Outcome::FrameLimit => 3,
+            Outcome::Fault(_) => 4,
+        }
+    }
+}
+
+/// Keys to hold from given frames on, each until the next entry.
+#[derive(Default)]
+pub struct InputScript {
+    /// Entries by ascending start frame.
+    entries: Vec<(u32, Vec<u8>)>,
+}
+
+impl InputScript {
+    /// Parses lines of `<FRAME> <KEYS>`, with keys as hex digits separated by spaces, or `-` for no keys.
+    /// Empty lines and lines starting with # are ignored.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut entries: Vec<(u32, Vec<u8>)> = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let mut words = line.split_whitespace();
+            let frame = match words.next() {
+                None => continue,
+                Some(word) if word.starts_with('#') => continue,
+                Some(word) => word.parse::<u32>().map_err(|_| format!("Line {}: Invalid frame: {}.", line_number, word))?,
+            };
+            if entries.last().is_some_and(|&(last_frame, _)| frame <= last_frame) {
+                return Err(format!("Line {}: Frames must be in ascending order.", line_number));
+            }
+            let mut keys = vec![];
+            for word in words.filter(|&word| word != "-") {
+                let key = u8::from_str_radix(word, 16)
+                    .ok()
+                    .filter(|&key| key <= 0xF)
+                    .ok_or_else(|| format!("Line {}: Invalid key: {}.", line_number, word))?;
+                keys.push(key);
+            }
+            entries.push((frame, keys));
+        }
+        Ok(Self { entries })
+    }
+
+    /// The keys held during the given frame.
+    pub fn keys_at(&self, frame: u32) -> &[u8] {
+        let index = self.entries.partition_point(|&(start, _)| start <= frame);
+        match index {
+            0 => &[],
+            _ => &self.entries[index - 1].1,
+        }
+    }
+}
+
+/// Runs the emulator without any frontend, taking screenshots and recording frames along the way, then writes the
+/// requested dumps. When playing back a movie, the run stops at its end unless a frame limit is given.
+pub fn run(config: &Config, mut emulator: Emulator, mut recorder: Recorder, mut movie: Option<MovieSession>, options: &HeadlessOptions) -> Result<Outcome, String> {
+    let script = match &options.input_path {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|error| format!("Could not read input script {}: {}.", path.display(), error))?;
+            InputScript::parse(&text).map_err(|error| format!("{}: {}", path.display(), error))?
+        },
+        None => InputScript::default(),
+    };
+
+    let mut input = EmulatorInput::new();
+    let mut output = emulator.output();
+    let mut frame = 0;
+    let frame_limit = options.frames.or_else(|| {
+        let playback_frames = movie.as_ref().and_then(MovieSession::playback_frames)?;
+        Some(playback_frames.try_into().unwrap_or(u32::MAX))
+    });
+    let take_screenshots = |frame: u32, display: &Display| -> Result<(), String> {
+        for (_, path) in options.screenshots.iter().filter(|&&(screenshot_frame, _)| screenshot_frame == frame) {
+            Image::render(display, config.screenshot_scale, &config.colors).save_png(path)?;
+        }
+        Ok(())
+    };
+    take_screenshots(frame, emulator.display())?;
+    while !output.halted && frame_limit.is_none_or(|frames| frame < frames) {
+        input.keypad.release_all_keys();
+        for &key in script.keys_at(frame) {
+            input.keypad.set_key_pressed(key);
+        }
+        if let Some(movie) = &mut movie {
+            movie.next_frame(frame as usize, &mut input.keypad);
+        }
+        output = emulator.run_frame(input.clone());
+        frame += 1;
+        take_screenshots(frame, emulator.display())?;
+        recorder.record(emulator.display(), emulator.settings().frame_rate)?;
+    }
+    for path in recorder.finish()? {
+        eprintln!("Saved recording to {}.", path.display());
+    }
+    if let Some(path) = movie.map(MovieSession::finish).transpose()?.flatten() {
+        eprintln!("Saved movie to {}.", path.display());
+    }
+    for (_, path) in options.screenshots.iter().filter(|&&(screenshot_frame, _)| screenshot_frame > frame) {
+        eprintln!("Skipped screenshot {}, as the run ended after {} frames.", path.display(), frame);
+    }
+
+    let outcome = match output.fault {
+        Some(fault) => Outcome::Fault(fault),
+        None if output.halted => Outcome::Exited,
+        None => Outcome::FrameLimit,
+    };
+    match &outcome {
+        Outcome::Exited => eprintln!("Program exited after {} frames.", frame),
+        Outcome::FrameLimit => eprintln!("Stopped after {} frames.", frame),
+        Outcome::Fault(fault) => eprintln!("Program halted by fault after {} frames: {}\n{}", frame, fault, debugger::stack(emulator.state())),
+    }
+
+    let state = emulator.state();
+    if let Some(path) = &options.display_path {
+        write(path, display_text(emulator.display()).as_bytes())?;
+    }
+    if let Some(path) = &options.registers_path {
+        write(path, format!("{}\n", debugger::registers(state)).as_bytes())?;
+    }
+    if let Some(path) = &options.memory_path {
+        if path == Path::new("-") {
+            // Hexdumps are limited to 16-bit lengths, so 64 KiB of memory is dumped in two halves.
+            let hexdump: Vec<String> = (0..state.memory().len())
+                .step_by(HEXDUMP_CHUNK_SIZE)
+                .map(|address| debugger::hexdump(state, address as u16, HEXDUMP_CHUNK_SIZE as u16))
+                .collect();
+            write(path, format!("{}\n", hexdump.join("\n")).as_bytes())?;
+        } else {
+            write(path, state.memory())?;
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// The display as text, one line per row, with `.` for unlit pixels, `#` for the first plane, `+` for the second and `@` for both.
+pub fn display_text(display: &Display) -> String {
+    let mut text = String::new();
+    for y in 0..display.height {
+        text.extend((0..display.width).map(|x| match display.pixel(x, y) {
+            0 => '.',
+            1 => '#',
+            2 => '+',
+            _ => '@',
+        }));
+        text.push('\n');
+    }
+    text
+}
+
+/// Writes the data to the file at the given path, or to standard output if the path is `-`.
+fn write(path: &Path, data: &[u8]) -> Result<(), String> {
+    if path == Path::new("-") {
+        return io::stdout().write_all(data).map_err(|error| format!("Could not write to standard output: {}.", error));
+    }
+    fs::write(path, data).map_err(|error| format!("Could not write {}: {}.", path.display(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::{Instruction, Settings};
+
+    #[test]
+    fn test_input_script() {
+        let script = InputScript::parse("# frame keys\n10 5\n\n20 4 a\n30 -\n").unwrap();
+        assert!(script.keys_at(0).is_empty());
+        assert_eq!(script.keys_at(10), &[0x5]);
+        assert_eq!(script.keys_at(29), &[0x4, 0xA]);
+        assert!(script.keys_at(1000).is_empty());
+
+        assert!(InputScript::parse("10 G").is_err());
+        assert!(InputScript::parse("10 5\n5 4").is_err());
+    }
+
+    #[test]
+    fn test_run_until_halt() {
+        let program: Vec<u8> = [
+            Instruction::SkipIfKeyUp { register: 0x0 },
+            Instruction::Exit,
+            Instruction::Jump { address: 0x200 },
+        ].into_iter().flat_map(Instruction::encode_bytes).collect();
+        let config = Config::resolve(&[]).unwrap();
+        let recorder = || Recorder::new(&config, None, None).unwrap();
+        let options = HeadlessOptions { frames: Some(10), ..HeadlessOptions::default() };
+        let emulator = Emulator::new(Settings::default(), program.clone()).unwrap();
+        assert_eq!(run(&config, emulator, recorder(), None, &options), Ok(Outcome::FrameLimit));
+
+        let script = std::env::temp_dir().join(format!("chip8-headless-{}.txt", std::process::id()));
+        fs::write(&script, "5 0\n").unwrap();
+        let options = HeadlessOptions { input_path: Some(script.clone()), ..options };
+        let emulator = Emulator::new(Settings::default(), program).unwrap();
+        let outcome = run(&config, emulator, recorder(), None, &options);
+        fs::remove_file(&script).unwrap();
+        assert_eq!(outcome, Ok(Outcome::Exited));
+    }
+}
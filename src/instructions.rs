@@ -1,9 +1,26 @@
+use std::fmt;
+use crate::settings::Settings;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Instruction {
     /// 00E0 - Clear the screen.
     ClearScreen,
     /// 00EE - Return from subroutine.
     Return,
+    /// 00CN - Scroll the display down by N pixels (SUPER-CHIP).
+    ScrollDown { rows: u8 },
+    /// 00DN - Scroll the display up by N pixels (XO-CHIP).
+    ScrollUp { rows: u8 },
+    /// 00FB - Scroll the display right by 4 pixels (SUPER-CHIP).
+    ScrollRight,
+    /// 00FC - Scroll the display left by 4 pixels (SUPER-CHIP).
+    ScrollLeft,
+    /// 00FD - Exit the interpreter (SUPER-CHIP).
+    Exit,
+    /// 00FE - Switch to low resolution mode (SUPER-CHIP).
+    LowResolution,
+    /// 00FF - Switch to high resolution mode (SUPER-CHIP).
+    HighResolution,
     /// 0NNN - Execute system subroutine (ignored).
     SysCall { address: u16 },
     /// 1NNN - Jump to address NNN.
@@ -14,6 +31,10 @@ pub enum Instruction {
     Call { address: u16 },
     /// 5XY0 - Skip next instruction if the value in register VX equals the value in register VY.
     SkipIfEqual { register: u8, comparand_register: u8 },
+    /// 5XY2 - Write the values in registers VX - VY into memory starting at the address in I, without changing I (XO-CHIP).
+    WriteRegisterRange { register: u8, end_register: u8 },
+    /// 5XY3 - Read memory starting at the address in I into registers VX - VY, without changing I (XO-CHIP).
+    ReadRegisterRange { register: u8, end_register: u8 },
     /// 9XY0 - Skip next instruction if the value in register VX does not equal the value in register VY.
     SkipIfNotEqual { register: u8, comparand_register: u8 },
     /// 3XNN - Skip next instruction if the value in register VX equals NN.
@@ -46,6 +67,9 @@ pub enum Instruction {
     Random { register: u8, mask: u8 },
     /// DXYN - Draw sprite at position (VX, VY). The sprite consists of N bytes starting from the address in I.
     DrawSprite { register_x: u8, register_y: u8, length: u8 },
+    /// DXY0 - Draw 16x16 sprite at position (VX, VY). The sprite consists of 32 bytes starting from the address in I (SUPER-CHIP).
+    /// Depending on the platform, an 8x16 sprite is drawn in low resolution mode, or nothing at all.
+    DrawLargeSprite { register_x: u8, register_y: u8 },
     /// EX9E - Skip next instruction if the key corresponding to the value in register VX is pressed.
     SkipIfKeyDown { register: u8 },
     /// EXA1 - Skip next instruction if the key corresponding to the value in register VX is not pressed.
@@ -60,21 +84,57 @@ pub enum Instruction {
     SetSoundTimer { register: u8 },
     /// ANNN - Load address NNN into I.
     LoadAddress { address: u16 },
+    /// F000 NNNN - Load the 16-bit address NNNN into I. This instruction occupies four bytes (XO-CHIP).
+    LoadLongAddress { address: u16 },
+    /// FN01 - Select the bitplanes N to draw, clear and scroll (XO-CHIP).
+    SelectPlanes { planes: u8 },
+    /// F002 - Load 16 bytes starting at the address in I into the audio pattern buffer (XO-CHIP).
+    LoadAudioPattern,
+    /// FX3A - Set the audio pattern playback pitch to the value in register VX (XO-CHIP).
+    SetPitch { register: u8 },
     /// FX1E - Add the value in register VX to I.
     AddToAddress { register: u8 },
     /// FX29 - Load the address of the sprite corresponding to the value stored in register VX into I.
     LoadDigitSpriteAddress { register: u8 },
+    /// FX30 - Load the address of the large sprite corresponding to the value stored in register VX into I (SUPER-CHIP).
+    LoadLargeDigitSpriteAddress { register: u8 },
     /// FX33 - Write the value in register VX as a binary-coded decimal into memory starting at the address in I.
     WriteMemoryFromBinaryCodedDecimal { register: u8 },
     /// FX55 - Write the values in registers V0 - VX into memory starting at the address in I.
     WriteMemory { end_register: u8 },
     /// FX65 - Read memory starting at the address in I into registers V0 - VX.
     ReadMemory { end_register: u8 },
+    /// FX75 - Write the values in registers V0 - VX into the RPL user flags (SUPER-CHIP).
+    WriteFlags { end_register: u8 },
+    /// FX85 - Read the RPL user flags into registers V0 - VX (SUPER-CHIP).
+    ReadFlags { end_register: u8 },
     /// Unknown / unsupported instruction.
     Unknown { instruction: u16 },
 }
 
 impl Instruction {
+    /// Whether the instruction is followed by a 16-bit operand, i.e. occupies four bytes rather than two.
+    pub fn is_long(instruction: u16) -> bool {
+        instruction == 0xF000
+    }
+
+    /// Decodes an instruction along with the 16-bit word following it, which is only used by long instructions.
+    pub fn decode_long(instruction: u16, operand: u16) -> Self {
+        if Self::is_long(instruction) {
+            Self::LoadLongAddress { address: operand }
+        } else {
+            Self::decode(instruction)
+        }
+    }
+
+    /// The size of the instruction in bytes.
+    pub fn size(self) -> u16 {
+        match self {
+            Self::LoadLongAddress { .. } => 4,
+            _ => 2,
+        }
+    }
+
     pub fn decode(instruction: u16) -> Self {
         let n0 = ((instruction & 0xF000) >> 12) as u8;
         let n1 = ((instruction & 0x0F00) >> 8) as u8;
@@ -83,12 +143,21 @@ impl Instruction {
         match (n0, n1, n2, n3) {
            (0x0, 0x0, 0xE, 0x0) => Self::ClearScreen,
            (0x0, 0x0, 0xE, 0xE) => Self::Return,
+           (0x0, 0x0, 0xC,   _) => Self::ScrollDown { rows: n3 },
+           (0x0, 0x0, 0xD,   _) => Self::ScrollUp { rows: n3 },
+           (0x0, 0x0, 0xF, 0xB) => Self::ScrollRight,
+           (0x0, 0x0, 0xF, 0xC) => Self::ScrollLeft,
+           (0x0, 0x0, 0xF, 0xD) => Self::Exit,
+           (0x0, 0x0, 0xF, 0xE) => Self::LowResolution,
+           (0x0, 0x0, 0xF, 0xF) => Self::HighResolution,
            (0x0,   _,   _,   _) => Self::SysCall { address: instruction },
            (0x1,   _,   _,   _) => Self::Jump { address: instruction & 0x0FFF },
            (0x2,   _,   _,   _) => Self::Call { address: instruction & 0x0FFF },
            (0x3,   _,   _,   _) => Self::SkipIfValue { register: n1, comparand_value: n2 << 4 | n3 },
            (0x4,   _,   _,   _) => Self::SkipIfNotValue { register: n1, comparand_value: n2 << 4 | n3 },
            (0x5,   _,   _, 0x0) => Self::SkipIfEqual { register: n1, comparand_register: n2 },
+           (0x5,   _,   _, 0x2) => Self::WriteRegisterRange { register: n1, end_register: n2 },
+           (0x5,   _,   _, 0x3) => Self::ReadRegisterRange { register: n1, end_register: n2 },
            (0x6,   _,   _,   _) => Self::LoadValue { register: n1, value: n2 << 4 | n3 },
            (0x7,   _,   _,   _) => Self::AddValue { register: n1, value: n2 << 4 | n3 },
            (0x8,   _,   _, 0x0) => Self::Load { register: n1, from_register: n2 },
@@ -104,23 +173,30 @@ impl Instruction {
            (0xA,   _,   _,   _) => Self::LoadAddress { address: instruction & 0x0FFF },
            (0xB,   _,   _,   _) => Self::JumpWithOffset { address: instruction & 0x0FFF },
            (0xC,   _,   _,   _) => Self::Random { register: n1, mask: n2 << 4 | n3 },
+           (0xD,   _,   _, 0x0) => Self::DrawLargeSprite { register_x: n1, register_y: n2 },
            (0xD,   _,   _,   _) => Self::DrawSprite { register_x: n1, register_y: n2, length: n3 },
            (0xE,   _, 0x9, 0xE) => Self::SkipIfKeyDown { register: n1 },
            (0xE,   _, 0xA, 0x1) => Self::SkipIfKeyUp { register: n1 },
+           (0xF,   _, 0x0, 0x1) => Self::SelectPlanes { planes: n1 },
+           (0xF, 0x0, 0x0, 0x2) => Self::LoadAudioPattern,
            (0xF,   _, 0x0, 0x7) => Self::LoadDelayTimer { register: n1 },
            (0xF,   _, 0x0, 0xA) => Self::WaitForKeyDown { register: n1 },
            (0xF,   _, 0x1, 0x5) => Self::SetDelayTimer { register: n1 },
            (0xF,   _, 0x1, 0x8) => Self::SetSoundTimer { register: n1 },
            (0xF,   _, 0x1, 0xE) => Self::AddToAddress { register: n1 },
            (0xF,   _, 0x2, 0x9) => Self::LoadDigitSpriteAddress { register: n1 },
+           (0xF,   _, 0x3, 0x0) => Self::LoadLargeDigitSpriteAddress { register: n1 },
+           (0xF,   _, 0x3, 0xA) => Self::SetPitch { register: n1 },
            (0xF,   _, 0x3, 0x3) => Self::WriteMemoryFromBinaryCodedDecimal { register: n1 },
            (0xF,   _, 0x5, 0x5) => Self::WriteMemory { end_register: n1 },
            (0xF,   _, 0x6, 0x5) => Self::ReadMemory { end_register: n1 },
+           (0xF,   _, 0x7, 0x5) => Self::WriteFlags { end_register: n1 },
+           (0xF,   _, 0x8, 0x5) => Self::ReadFlags { end_register: n1 },
            _                    => Self::Unknown { instruction }
         }
     }
 
-    #[cfg(test)]
+    /// Encodes the instruction as an opcode. Long instructions are followed by an operand, which `encode_bytes` includes.
     pub fn encode(self) -> u16 {
         fn concat(n0: u8, n1: u8, n2: u8, n3: u8) -> u16 {
             (n0 as u16) << 12 | (n1 as u16) << 8 | (n2 as u16) << 4 | n3 as u16
@@ -128,12 +204,21 @@ impl Instruction {
         match self {
             Self::ClearScreen                                       => 0x00E0,
             Self::Return                                            => 0x00EE,
+            Self::ScrollDown { rows }                               => 0x00C0 | rows as u16,
+            Self::ScrollUp { rows }                                 => 0x00D0 | rows as u16,
+            Self::ScrollRight                                       => 0x00FB,
+            Self::ScrollLeft                                        => 0x00FC,
+            Self::Exit                                              => 0x00FD,
+            Self::LowResolution                                     => 0x00FE,
+            Self::HighResolution                                    => 0x00FF,
             Self::SysCall { address }                               => address,
             Self::Jump { address }                                  => 0x1000 | address,
             Self::Call { address }                                  => 0x2000 | address,
             Self::SkipIfValue { register, comparand_value }         => concat(0x3, register, 0, comparand_value),
             Self::SkipIfNotValue { register, comparand_value }      => concat(0x4, register, 0, comparand_value),
             Self::SkipIfEqual { register, comparand_register }      => concat(0x5, register, comparand_register, 0x0),
+            Self::WriteRegisterRange { register, end_register }     => concat(0x5, register, end_register, 0x2),
+            Self::ReadRegisterRange { register, end_register }      => concat(0x5, register, end_register, 0x3),
             Self::LoadValue { register, value }                     => concat(0x6, register, 0, value),
             Self::AddValue { register, value }                      => concat(0x7, register, 0, value),
             Self::Load { register, from_register }                  => concat(0x8, register, from_register, 0x0),
@@ -150,36 +235,138 @@ impl Instruction {
             Self::JumpWithOffset { address }                        => 0xB000 | address,
             Self::Random { register, mask }                         => concat(0xC, register, 0, mask),
             Self::DrawSprite { register_x, register_y, length }     => concat(0xD, register_x, register_y, length),
+            Self::DrawLargeSprite { register_x, register_y }        => concat(0xD, register_x, register_y, 0x0),
             Self::SkipIfKeyDown { register }                        => concat(0xE, register, 0x9, 0xE),
             Self::SkipIfKeyUp { register }                          => concat(0xE, register, 0xA, 0x1),
+            Self::SelectPlanes { planes }                           => concat(0xF, planes, 0x0, 0x1),
+            Self::LoadAudioPattern                                  => 0xF002,
             Self::LoadDelayTimer { register }                       => concat(0xF, register, 0x0, 0x7),
             Self::WaitForKeyDown { register }                       => concat(0xF, register, 0x0, 0xA),
             Self::SetDelayTimer { register }                        => concat(0xF, register, 0x1, 0x5),
             Self::SetSoundTimer { register }                        => concat(0xF, register, 0x1, 0x8),
+            Self::LoadLongAddress { .. }                            => 0xF000,
+            Self::SetPitch { register }                             => concat(0xF, register, 0x3, 0xA),
             Self::AddToAddress { register }                         => concat(0xF, register, 0x1, 0xE),
             Self::LoadDigitSpriteAddress { register }               => concat(0xF, register, 0x2, 0x9),
+            Self::LoadLargeDigitSpriteAddress { register }          => concat(0xF, register, 0x3, 0x0),
             Self::WriteMemoryFromBinaryCodedDecimal { register }    => concat(0xF, register, 0x3, 0x3),
             Self::WriteMemory { end_register }                      => concat(0xF, end_register, 0x5, 0x5),
             Self::ReadMemory { end_register }                       => concat(0xF, end_register, 0x6, 0x5),
+            Self::WriteFlags { end_register }                       => concat(0xF, end_register, 0x7, 0x5),
+            Self::ReadFlags { end_register }                        => concat(0xF, end_register, 0x8, 0x5),
             Self::Unknown { instruction }                           => instruction
         }
     }
+
+    /// Encodes the instruction as big-endian bytes, including the operand of long instructions.
+    pub fn encode_bytes(self) -> Vec<u8> {
+        let instruction = self.encode();
+        let mut bytes = vec![(instruction >> 8) as u8, (instruction & 0x00FF) as u8];
+        if let Self::LoadLongAddress { address } = self {
+            bytes.extend_from_slice(&[(address >> 8) as u8, (address & 0x00FF) as u8]);
+        }
+        bytes
+    }
+
+    /// Formats the instruction like its `Display` form, except that BNNN names the register it jumps by under the
+    /// flexible jump offset quirk (SUPER-CHIP's BXNN), e.g. `JP V1, 0x123` rather than `JP V0, 0x123`.
+    pub fn format(self, settings: &Settings) -> String {
+        match self {
+            Self::JumpWithOffset { address } if settings.use_flexible_jump_offset => {
+                format!("JP V{:X}, 0x{:03X}", address >> 8, address)
+            },
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// Formats the instruction using conventional mnemonics, e.g. `SE V1, 0x23` or `DRW V1, V2, 3`. The SUPER-CHIP and
+    /// XO-CHIP instructions follow Octo, e.g. `HIRES` or `LD [I], V1 - V3`, and the long XO-CHIP form of `LD I` shows
+    /// a 16-bit address.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::ClearScreen                                       => write!(f, "CLS"),
+            Self::Return                                            => write!(f, "RET"),
+            Self::ScrollDown { rows }                               => write!(f, "SCD {}", rows),
+            Self::ScrollUp { rows }                                 => write!(f, "SCU {}", rows),
+            Self::ScrollRight                                       => write!(f, "SCR"),
+            Self::ScrollLeft                                        => write!(f, "SCL"),
+            Self::Exit                                              => write!(f, "EXIT"),
+            Self::LowResolution                                     => write!(f, "LORES"),
+            Self::HighResolution                                    => write!(f, "HIRES"),
+            Self::SysCall { address }                               => write!(f, "SYS 0x{:03X}", address),
+            Self::Jump { address }                                  => write!(f, "JP 0x{:03X}", address),
+            Self::JumpWithOffset { address }                        => write!(f, "JP V0, 0x{:03X}", address),
+            Self::Call { address }                                  => write!(f, "CALL 0x{:03X}", address),
+            Self::SkipIfValue { register, comparand_value }         => write!(f, "SE V{:X}, 0x{:02X}", register, comparand_value),
+            Self::SkipIfNotValue { register, comparand_value }      => write!(f, "SNE V{:X}, 0x{:02X}", register, comparand_value),
+            Self::SkipIfEqual { register, comparand_register }      => write!(f, "SE V{:X}, V{:X}", register, comparand_register),
+            Self::SkipIfNotEqual { register, comparand_register }   => write!(f, "SNE V{:X}, V{:X}", register, comparand_register),
+            Self::WriteRegisterRange { register, end_register }     => write!(f, "LD [I], V{:X} - V{:X}", register, end_register),
+            Self::ReadRegisterRange { register, end_register }      => write!(f, "LD V{:X} - V{:X}, [I]", register, end_register),
+            Self::LoadValue { register, value }                     => write!(f, "LD V{:X}, 0x{:02X}", register, value),
+            Self::AddValue { register, value }                      => write!(f, "ADD V{:X}, 0x{:02X}", register, value),
+            Self::Load { register, from_register }                  => write!(f, "LD V{:X}, V{:X}", register, from_register),
+            Self::Or { register, or_register }                      => write!(f, "OR V{:X}, V{:X}", register, or_register),
+            Self::And { register, and_register }                    => write!(f, "AND V{:X}, V{:X}", register, and_register),
+            Self::Xor { register, xor_register }                    => write!(f, "XOR V{:X}, V{:X}", register, xor_register),
+            Self::Add { register, add_register }                    => write!(f, "ADD V{:X}, V{:X}", register, add_register),
+            Self::Subtract { register, subtract_register }          => write!(f, "SUB V{:X}, V{:X}", register, subtract_register),
+            Self::SubtractFrom { register, subtract_from_register } => write!(f, "SUBN V{:X}, V{:X}", register, subtract_from_register),
+            Self::ShiftRight { register, source_register }          => write!(f, "SHR V{:X}, V{:X}", register, source_register),
+            Self::ShiftLeft { register, source_register }           => write!(f, "SHL V{:X}, V{:X}", register, source_register),
+            Self::Random { register, mask }                         => write!(f, "RND V{:X}, 0x{:02X}", register, mask),
+            Self::DrawSprite { register_x, register_y, length }     => write!(f, "DRW V{:X}, V{:X}, {}", register_x, register_y, length),
+            Self::DrawLargeSprite { register_x, register_y }        => write!(f, "DRW V{:X}, V{:X}, 0", register_x, register_y),
+            Self::SkipIfKeyDown { register }                        => write!(f, "SKP V{:X}", register),
+            Self::SkipIfKeyUp { register }                          => write!(f, "SKNP V{:X}", register),
+            Self::WaitForKeyDown { register }                       => write!(f, "LD V{:X}, K", register),
+            Self::LoadDelayTimer { register }                       => write!(f, "LD V{:X}, DT", register),
+            Self::SetDelayTimer { register }                        => write!(f, "LD DT, V{:X}", register),
+            Self::SetSoundTimer { register }                        => write!(f, "LD ST, V{:X}", register),
+            Self::LoadAddress { address }                           => write!(f, "LD I, 0x{:03X}", address),
+            Self::LoadLongAddress { address }                       => write!(f, "LD I, 0x{:04X}", address),
+            Self::SelectPlanes { planes }                           => write!(f, "PLANE {}", planes),
+            Self::LoadAudioPattern                                  => write!(f, "AUDIO"),
+            Self::SetPitch { register }                             => write!(f, "PITCH V{:X}", register),
+            Self::AddToAddress { register }                         => write!(f, "ADD I, V{:X}", register),
+            Self::LoadDigitSpriteAddress { register }               => write!(f, "LD F, V{:X}", register),
+            Self::LoadLargeDigitSpriteAddress { register }          => write!(f, "LD HF, V{:X}", register),
+            Self::WriteMemoryFromBinaryCodedDecimal { register }    => write!(f, "LD B, V{:X}", register),
+            Self::WriteMemory { end_register }                      => write!(f, "LD [I], V{:X}", end_register),
+            Self::ReadMemory { end_register }                       => write!(f, "LD V{:X}, [I]", end_register),
+            Self::WriteFlags { end_register }                       => write!(f, "LD R, V{:X}", end_register),
+            Self::ReadFlags { end_register }                        => write!(f, "LD V{:X}, R", end_register),
+            Self::Unknown { instruction }                           => write!(f, "DW 0x{:04X}", instruction),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::settings::Platform;
 
     fn generate_instructions() -> Vec<(u16, Instruction)> {
         vec![
             (0x00E0u16, Instruction::ClearScreen),
             (0x00EEu16, Instruction::Return),
+            (0x00C5u16, Instruction::ScrollDown { rows: 0x5 }),
+            (0x00D5u16, Instruction::ScrollUp { rows: 0x5 }),
+            (0x00FBu16, Instruction::ScrollRight),
+            (0x00FCu16, Instruction::ScrollLeft),
+            (0x00FDu16, Instruction::Exit),
+            (0x00FEu16, Instruction::LowResolution),
+            (0x00FFu16, Instruction::HighResolution),
             (0x0123u16, Instruction::SysCall { address: 0x123 }),
             (0x1123u16, Instruction::Jump { address: 0x123 }),
             (0x2123u16, Instruction::Call { address: 0x123 }),
             (0x3123u16, Instruction::SkipIfValue { register: 0x1, comparand_value: 0x23 }),
             (0x4123u16, Instruction::SkipIfNotValue { register: 0x1, comparand_value: 0x23 }),
             (0x5120u16, Instruction::SkipIfEqual { register: 0x1, comparand_register: 0x2 }),
+            (0x5122u16, Instruction::WriteRegisterRange { register: 0x1, end_register: 0x2 }),
+            (0x5123u16, Instruction::ReadRegisterRange { register: 0x1, end_register: 0x2 }),
             (0x6123u16, Instruction::LoadValue { register: 0x1, value: 0x23 }),
             (0x7123u16, Instruction::AddValue { register: 0x1, value: 0x23 }),
             (0x8120u16, Instruction::Load { register: 0x1, from_register: 0x2 }),
@@ -196,17 +383,24 @@ mod tests {
             (0xB123u16, Instruction::JumpWithOffset { address: 0x123 }),
             (0xC123u16, Instruction::Random { register: 0x1, mask: 0x23 }),
             (0xD123u16, Instruction::DrawSprite { register_x: 0x1, register_y: 0x2, length: 0x3 }),
+            (0xD120u16, Instruction::DrawLargeSprite { register_x: 0x1, register_y: 0x2 }),
             (0xE19Eu16, Instruction::SkipIfKeyDown { register: 0x1 }),
             (0xE1A1u16, Instruction::SkipIfKeyUp { register: 0x1 }),
+            (0xF201u16, Instruction::SelectPlanes { planes: 0x2 }),
+            (0xF002u16, Instruction::LoadAudioPattern),
             (0xF107u16, Instruction::LoadDelayTimer { register: 0x1 }),
             (0xF10Au16, Instruction::WaitForKeyDown { register: 0x1 }),
             (0xF115u16, Instruction::SetDelayTimer { register: 0x1 }),
             (0xF118u16, Instruction::SetSoundTimer { register: 0x1 }),
             (0xF11Eu16, Instruction::AddToAddress { register: 0x1 }),
             (0xF129u16, Instruction::LoadDigitSpriteAddress { register: 0x1 }),
+            (0xF130u16, Instruction::LoadLargeDigitSpriteAddress { register: 0x1 }),
+            (0xF13Au16, Instruction::SetPitch { register: 0x1 }),
             (0xF133u16, Instruction::WriteMemoryFromBinaryCodedDecimal { register: 0x1 }),
             (0xF155u16, Instruction::WriteMemory { end_register: 0x1 }),
             (0xF165u16, Instruction::ReadMemory { end_register: 0x1 }),
+            (0xF175u16, Instruction::WriteFlags { end_register: 0x1 }),
+            (0xF185u16, Instruction::ReadFlags { end_register: 0x1 }),
             (0x9BCDu16, Instruction::Unknown { instruction: 0x9BCD }),
         ]
     }
@@ -218,10 +412,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn can_decode_long_instruction() {
+        assert!(Instruction::is_long(0xF000));
+        assert_eq!(Instruction::decode_long(0xF000, 0xABCD), Instruction::LoadLongAddress { address: 0xABCD });
+        assert_eq!(Instruction::decode_long(0x00E0, 0xABCD), Instruction::ClearScreen);
+        assert_eq!(Instruction::LoadLongAddress { address: 0xABCD }.encode_bytes(), vec![0xF0, 0x00, 0xAB, 0xCD]);
+    }
+
     #[test]
     fn can_encode_instruction() {
         for (encoded, decoded) in generate_instructions() {
             assert_eq!(encoded, decoded.encode());
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn can_display_instruction() {
+        assert_eq!(Instruction::SkipIfValue { register: 0x1, comparand_value: 0x23 }.to_string(), "SE V1, 0x23");
+        assert_eq!(Instruction::DrawSprite { register_x: 0x1, register_y: 0x2, length: 0x3 }.to_string(), "DRW V1, V2, 3");
+        assert_eq!(Instruction::LoadLongAddress { address: 0xABCD }.to_string(), "LD I, 0xABCD");
+        assert_eq!(Instruction::ReadMemory { end_register: 0xF }.to_string(), "LD VF, [I]");
+        assert_eq!(Instruction::WriteRegisterRange { register: 0x1, end_register: 0x3 }.to_string(), "LD [I], V1 - V3");
+        assert_eq!(Instruction::HighResolution.to_string(), "HIRES");
+    }
+
+    #[test]
+    fn can_format_jump_with_offset_by_platform() {
+        let jump = Instruction::JumpWithOffset { address: 0x123 };
+        assert_eq!(jump.format(&Settings::preset(Platform::CosmacVip)), "JP V0, 0x123");
+        assert_eq!(jump.format(&Settings::preset(Platform::SuperChip11)), "JP V1, 0x123");
+    }
+}
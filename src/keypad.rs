@@ -1,3 +1,5 @@
+use crate::savestate::{Reader, SaveStateError, Writer};
+
 const KEY_COUNT: usize = 16;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -53,4 +55,33 @@ impl Keypad {
         }
         keys
     }
+
+    pub(crate) fn save_to(&self, writer: &mut Writer) {
+        for key in self.keys {
+            writer.u8(match key {
+                KeyPressState::None => 0,
+                KeyPressState::Pressed => 1,
+                KeyPressState::Released => 2,
+            });
+        }
+    }
+
+    pub(crate) fn load_from(reader: &mut Reader) -> Result<Self, SaveStateError> {
+        let mut keypad = Self::new();
+        for key in keypad.keys.iter_mut() {
+            *key = match reader.u8()? {
+                0 => KeyPressState::None,
+                1 => KeyPressState::Pressed,
+                2 => KeyPressState::Released,
+                _ => return Err(SaveStateError::InvalidValue("key state")),
+            };
+        }
+        Ok(keypad)
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Self::new()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,28 @@
+pub mod assembler;
+pub mod audio;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod emulator;
+pub mod instructions;
+pub mod keypad;
+pub mod movie;
+pub mod random;
+pub mod rewind;
+pub mod savestate;
+pub mod settings;
+pub mod state;
+
+pub use audio::AudioPattern;
+pub use cpu::{Cpu, CpuCycleResult, CpuError};
+pub use debugger::{Debugger, DebuggerCommand};
+pub use display::{DirtyRows, Display};
+pub use emulator::{Emulator, EmulatorInput, EmulatorOutput};
+pub use instructions::Instruction;
+pub use keypad::Keypad;
+pub use movie::{Movie, MovieError};
+pub use random::Random;
+pub use savestate::SaveStateError;
+pub use settings::Settings;
+pub use state::{State, StateError};
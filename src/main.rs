@@ -1,132 +1,157 @@
+#[cfg(feature = "sdl")]
 extern crate sdl2;
 
+mod cli;
+mod config;
+mod headless;
+mod movie_session;
+mod phosphor;
+mod recording;
+mod rom;
+mod romdb;
+mod screenshot;
+#[cfg(feature = "sdl")]
+mod sdl_frontend;
+#[cfg(feature = "sdl")]
 mod speaker;
-mod cpu;
-mod display;
-mod emulator;
-mod instructions;
-mod keypad;
-mod settings;
-mod state;
-
-use emulator::{Emulator, EmulatorInput};
-use settings::Settings;
-use speaker::{SpeakerSettings, Speaker};
-use sdl2::event::Event;
-use sdl2::keyboard::{Keycode, Scancode};
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
+mod terminal_frontend;
+
+use chip8::assembler;
+use chip8::disassembler::Disassembly;
+use chip8::{Debugger, Emulator};
+use cli::{Command, Options};
+use config::{Config, ConfigFile};
+use movie_session::MovieSession;
+use recording::Recorder;
+use rom::Rom;
 use std::env;
 use std::fs;
-
-const WINDOW_WIDTH: u32 = 640;
-const WINDOW_HEIGHT: u32 = 320;
+use std::path::Path;
+use std::process;
 
 fn main() {
-    let mut args = env::args();
-    let path = args.nth(1).expect("Please provide a path to a valid program.");
-    let program = fs::read(path).expect("Could not load program.");
-
-    let emulator_settings = Settings::default();
-    let mut emulator = Emulator::new(emulator_settings, program);
-
-    let sdl_context = sdl2::init().expect("Could not initialize SDL2.");
-
-    let audio_subsystem = sdl_context.audio().expect("Could not initialize audio subsystem.");
-    let speaker_settings = SpeakerSettings::default();
-    let mut speaker = Speaker::new(audio_subsystem, speaker_settings);
-
-    let video_subsystem = sdl_context.video().expect("Could not initialize video subsystem.");
-    let window = video_subsystem
-        .window("Chip-8", WINDOW_WIDTH, WINDOW_HEIGHT)
-        .position_centered()
-        .resizable()
-        .build()
-        .expect("Could not build window.");
-    let mut canvas = window.into_canvas().build().expect("Could not build canvas.");
-    let mut event_pump = sdl_context.event_pump().expect("Could not obtain event pump.");
-
-    let mut input = EmulatorInput::new();
-
-    emulator.execute(|output| {
-        if output.sound_playing {
-            speaker.play()
-        } else {
-            speaker.pause()
-        }
-
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit {..} |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    return EmulatorInput::quit();
-                },
-                Event::KeyDown { keycode: Some(Keycode::M), .. } => {
-                    if speaker.volume() > 0 {
-                        speaker.set_volume(0);
-                    } else {
-                        speaker.set_volume(speaker_settings.volume);
-                    }
-                },
-                _ => {}
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Run(options)) => *options,
+        Ok(Command::Disassemble { program_path, settings }) => {
+            match Rom::load(&program_path) {
+                Ok(rom) => print!("{}", Disassembly::new(&rom.data, &settings).listing()),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    process::exit(1);
+                }
             }
-        }
-
-        input.keypad.release_all_keys();
-        for scancode in event_pump.keyboard_state().pressed_scancodes() {
-            let key_pressed = match scancode {
-                Scancode::Num1 => Some(0x1),
-                Scancode::Num2 => Some(0x2),
-                Scancode::Num3 => Some(0x3),
-                Scancode::Num4 => Some(0xC),
-                Scancode::Q => Some(0x4),
-                Scancode::W => Some(0x5),
-                Scancode::E => Some(0x6),
-                Scancode::R => Some(0xD),
-                Scancode::A => Some(0x7),
-                Scancode::S => Some(0x8),
-                Scancode::D => Some(0x9),
-                Scancode::F => Some(0xE),
-                Scancode::Z => Some(0xA),
-                Scancode::X => Some(0x0),
-                Scancode::C => Some(0xB),
-                Scancode::V => Some(0xF),
-                _ => None
-            };
-
-            if let Some(key) = key_pressed  {
-                input.keypad.set_key_pressed(key);
+            return;
+        },
+        Ok(Command::Assemble { source_path, output_path, start_address }) => {
+            if let Err(error) = assemble(&source_path, &output_path, start_address) {
+                eprintln!("{}", error);
+                process::exit(1);
             }
+            return;
+        },
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        },
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, cli::USAGE);
+            process::exit(2);
         }
-
-        let (window_width, window_height) = canvas.output_size().expect("Could not retrieve canvas output size.");
-        let width_scale = window_width / u32::from(output.display.width);
-        let heigh_scale = window_height / u32::from(output.display.height);
-
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-
-        canvas.set_draw_color(Color::RGB(255, 255, 255));
-        for (x, y) in output.display.visible_pixels() {
-            let x_canvas = (x as u32 * width_scale) as i32;
-            let y_canvas = (y as u32 * heigh_scale) as i32;
-            canvas.fill_rect(Rect::new(x_canvas, y_canvas, width_scale, heigh_scale)).expect("Draw failed.");
+    };
+
+    match run(options) {
+        Ok(0) => {},
+        Ok(status) => process::exit(status),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
         }
-
-        canvas.present();
-        return input.clone();
-    });
-
-    loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit {..} |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    return;
-                },
-                _ => {}
+    }
+}
+
+/// Runs the program, yielding the exit status.
+fn run(options: Options) -> Result<i32, String> {
+    let config_file = match (&options.config_path, ConfigFile::default_path()) {
+        (Some(path), _) => ConfigFile::load(path, true)?,
+        (None, Some(path)) => ConfigFile::load(&path, false)?,
+        (None, None) => ConfigFile::default(),
+    };
+
+    let rom = options.program_path.as_deref().map(Rom::load).transpose()?;
+    let rom_profiles = rom.as_ref().map(|rom| config_file.rom_profiles(&rom.name, &rom.sha1)).unwrap_or_default();
+    let detection = match &rom {
+        Some(rom) if options.use_database => {
+            // The database speeds are per frame, so they depend on the frame rate set by any of the other profiles.
+            let frame_rate = [&config_file.defaults].into_iter().chain(rom_profiles.iter().copied()).chain([&options.profile])
+                .rev()
+                .find_map(|profile| profile.frame_rate)
+                .unwrap_or(chip8::Settings::default().frame_rate);
+            let detection = romdb::lookup(&rom.sha1, frame_rate);
+            match &detection {
+                Some(detection) => eprintln!("{}", detection.describe()),
+                None => eprintln!("ROM not found in the program database (SHA-1: {}). Using default settings.", rom.sha1),
             }
-        }
-        canvas.present();
+            detection
+        },
+        _ => None,
+    };
+
+    let mut later_profiles = rom_profiles;
+    later_profiles.push(&options.profile);
+    let detected_profile = detection.as_ref().map(|detection| detection.profile_before(&later_profiles));
+    let mut profiles = vec![&config_file.defaults];
+    profiles.extend(&detected_profile);
+    profiles.extend(later_profiles);
+    let mut config = Config::resolve(&profiles)?;
+
+    if options.print_config {
+        print!("{}", config.to_toml());
+        return Ok(0);
     }
-}
\ No newline at end of file
+
+    let rom = rom.ok_or("Please provide a path to a valid program.")?;
+    let playback = options.play_movie.as_deref().map(|path| MovieSession::play(path, &rom)).transpose()?;
+    if let Some(playback) = &playback {
+        // Movies can only be reproduced with the settings and seed they were recorded with.
+        config.settings = playback.settings();
+    }
+    let available_memory = config.settings.memory_size.saturating_sub(config.settings.program_start_address as u32);
+    if rom.data.len() as u32 > available_memory {
+        return Err(format!("Program is too large: {} bytes, but only {} bytes of memory are available.", rom.data.len(), available_memory));
+    }
+    let mut emulator = Emulator::new(config.settings, rom.data.clone()).map_err(|error| format!("Could not start the program: {}", error))?;
+    if config.settings.random_seed.is_none() {
+        let seed = emulator.state().random().seed();
+        eprintln!("Using random seed {}. Pass --seed {} to reproduce this run.", seed, seed);
+    }
+    let recorder = Recorder::new(&config, options.record_gif.as_deref(), options.record_y4m.as_deref())?;
+    let movie = playback.or_else(|| options.record_movie.as_deref().map(|path| MovieSession::record(path, &emulator, &rom)));
+    if let Some(headless_options) = &options.headless {
+        return headless::run(&config, emulator, recorder, movie, headless_options).map(|outcome| outcome.exit_status());
+    }
+    if let Some(charset) = options.terminal {
+        terminal_frontend::run(config, emulator, recorder, movie, charset)?;
+        return Ok(0);
+    }
+
+    emulator.set_rewind_capacity(config.rewind_frames());
+    let debugger = options.debug.then(Debugger::new);
+    run_frontend(config, emulator, debugger, recorder, movie, &rom)?;
+    Ok(0)
+}
+
+fn assemble(source_path: &Path, output_path: &Path, start_address: u16) -> Result<(), String> {
+    let source = fs::read_to_string(source_path).map_err(|error| format!("Could not read {}: {}.", source_path.display(), error))?;
+    let program = assembler::assemble(&source, start_address).map_err(|error| format!("{}:{}", source_path.display(), error))?;
+    fs::write(output_path, program).map_err(|error| format!("Could not write {}: {}.", output_path.display(), error))
+}
+
+#[cfg(feature = "sdl")]
+fn run_frontend(config: Config, emulator: Emulator, debugger: Option<Debugger>, recorder: Recorder, movie: Option<MovieSession>, rom: &Rom) -> Result<(), String> {
+    sdl_frontend::run(config, emulator, debugger, recorder, movie, rom)
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_frontend(_config: Config, _emulator: Emulator, _debugger: Option<Debugger>, _recorder: Recorder, _movie: Option<MovieSession>, _rom: &Rom) -> Result<(), String> {
+    Err("This build does not include the SDL2 frontend. Please rebuild with `--features sdl`, or use --terminal.".to_string())
+}
@@ -0,0 +1,229 @@
+use std::fmt;
+use crate::keypad::Keypad;
+use crate::savestate::{Reader, SaveStateError, Writer};
+use crate::settings::Settings;
+
+const MAGIC: &[u8; 4] = b"CH8M";
+pub const FORMAT_VERSION: u16 = 3;
+
+const KEY_COUNT: u8 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovieError {
+    /// The data does not start with the movie magic bytes.
+    InvalidHeader,
+    /// The movie was written in a format version this build cannot read.
+    UnsupportedVersion(u16),
+    /// The data ended before the movie was complete.
+    Truncated,
+    /// The movie contains a value that is out of range.
+    InvalidValue(&'static str),
+}
+
+impl From<SaveStateError> for MovieError {
+    fn from(error: SaveStateError) -> Self {
+        match error {
+            SaveStateError::InvalidHeader => MovieError::InvalidHeader,
+            SaveStateError::UnsupportedVersion(version) => MovieError::UnsupportedVersion(version),
+            SaveStateError::Truncated => MovieError::Truncated,
+            SaveStateError::InvalidValue(name) => MovieError::InvalidValue(name),
+        }
+    }
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::InvalidHeader => write!(f, "Not a movie."),
+            MovieError::UnsupportedVersion(version) => write!(f, "Unsupported movie version: {}.", version),
+            MovieError::Truncated => write!(f, "Movie is truncated."),
+            MovieError::InvalidValue(name) => write!(f, "Movie contains an invalid {}.", name),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+/// The keys held in every frame of a run from power-on. As execution is deterministic for given settings and seed,
+/// playing the input back reproduces the run exactly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    rom_sha1: String,
+    settings: Settings,
+    /// The held keys of each frame, as bitmasks by key.
+    frames: Vec<u16>,
+}
+
+impl Movie {
+    /// Creates an empty movie for the ROM with the given SHA-1 hash, fixing the seed of the random number generator.
+    pub fn new(rom_sha1: &str, settings: Settings, seed: u32) -> Self {
+        Self {
+            rom_sha1: rom_sha1.to_string(),
+            settings: Settings { random_seed: Some(seed), ..settings },
+            frames: vec![],
+        }
+    }
+
+    /// The lowercase hexadecimal SHA-1 hash of the ROM the movie was recorded with.
+    pub fn rom_sha1(&self) -> &str {
+        &self.rom_sha1
+    }
+
+    /// The settings the movie was recorded with, including the seed of the random number generator.
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    /// The number of recorded frames.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Records the keys held in the given frame, discarding any frames recorded after it, e.g. before rewinding.
+    pub fn record(&mut self, frame: usize, keypad: &Keypad) {
+        let keys = (0..KEY_COUNT).filter(|&key| keypad.key_pressed(key)).fold(0, |keys, key| keys | 1 << key);
+        self.frames.resize(frame, 0);
+        self.frames.push(keys);
+    }
+
+    /// The keypad state of the given frame, or `None` past the end of the movie. Keys held in the previous frame but
+    /// not in this one are released, just as frontends do by calling `release_all_keys` before pressing held keys.
+    pub fn keypad(&self, frame: usize) -> Option<Keypad> {
+        let keys = *self.frames.get(frame)?;
+        let previous_keys = frame.checked_sub(1).map_or(0, |previous| self.frames[previous]);
+        let mut keypad = Keypad::new();
+        for key in (0..KEY_COUNT).filter(|&key| previous_keys & 1 << key != 0) {
+            keypad.set_key_pressed(key);
+        }
+        keypad.release_all_keys();
+        for key in (0..KEY_COUNT).filter(|&key| keys & 1 << key != 0) {
+            keypad.set_key_pressed(key);
+        }
+        Some(keypad)
+    }
+
+    /// Serializes the movie into a versioned binary format, sharing the encoding of save states.
+    pub fn save(&self) -> Vec<u8> {
+        let mut writer = Writer::with_header(MAGIC, FORMAT_VERSION);
+        writer.vec(self.rom_sha1.as_bytes());
+        self.settings.save_to(&mut writer);
+        writer.u32(self.frames.len() as u32);
+        for &keys in &self.frames {
+            writer.u16(keys);
+        }
+        writer.finish()
+    }
+
+    /// Restores a movie serialized by `save`.
+    pub fn load(data: &[u8]) -> Result<Self, MovieError> {
+        let mut reader = Reader::with_header(data, MAGIC, FORMAT_VERSION)?;
+        let rom_sha1 = String::from_utf8(reader.vec()?).map_err(|_| MovieError::InvalidValue("ROM hash"))?;
+        let settings = Settings::load_from(&mut reader)?;
+        if settings.random_seed.is_none() {
+            return Err(MovieError::InvalidValue("random seed"));
+        }
+        let frame_count = reader.u32()?;
+        let frames = (0..frame_count).map(|_| reader.u16()).collect::<Result<Vec<u16>, _>>()?;
+        reader.finish()?;
+        Ok(Self { rom_sha1, settings, frames })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorInput};
+    use crate::instructions::Instruction;
+    use crate::state::State;
+
+    fn keypad(keys: &[u8]) -> Keypad {
+        let mut keypad = Keypad::new();
+        for &key in keys {
+            keypad.set_key_pressed(key);
+        }
+        keypad
+    }
+
+    #[test]
+    fn test_record_and_play() {
+        let mut movie = Movie::new("abc", Settings::default(), 42);
+        assert_eq!(movie.settings().random_seed, Some(42));
+        movie.record(0, &keypad(&[0x5]));
+        movie.record(1, &keypad(&[0x4, 0xF]));
+        movie.record(2, &keypad(&[]));
+
+        let first = movie.keypad(0).unwrap();
+        assert!(first.key_pressed(0x5));
+        let second = movie.keypad(1).unwrap();
+        assert!(second.key_pressed(0x4) && second.key_pressed(0xF));
+        assert_eq!(second.released_keys(), vec![0x5]);
+        assert_eq!(movie.keypad(2).unwrap().released_keys(), vec![0x4, 0xF]);
+        assert!(movie.keypad(3).is_none());
+
+        movie.record(1, &keypad(&[0x1]));
+        assert_eq!(movie.len(), 2);
+        assert!(movie.keypad(1).unwrap().key_pressed(0x1));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut movie = Movie::new("abc", Settings::default(), 42);
+        movie.record(0, &keypad(&[0x5]));
+        let data = movie.save();
+        assert_eq!(Movie::load(&data), Ok(movie));
+        assert_eq!(Movie::load(&data[.. data.len() - 1]), Err(MovieError::Truncated));
+        assert_eq!(Movie::load(b"CH8S\x00\x01"), Err(MovieError::InvalidHeader));
+    }
+
+    #[test]
+    fn test_play_back_recorded_run() {
+        // Draws at random positions, and another sprite while key 5 is held, so the run depends on the seed and input.
+        let program: Vec<u8> = [
+            Instruction::LoadValue { register: 0x2, value: 0x5 },
+            Instruction::Random { register: 0x0, mask: 0x3F },
+            Instruction::Random { register: 0x1, mask: 0x1F },
+            Instruction::SkipIfKeyUp { register: 0x2 },
+            Instruction::DrawSprite { register_x: 0x1, register_y: 0x0, length: 5 },
+            Instruction::DrawSprite { register_x: 0x0, register_y: 0x1, length: 1 },
+            Instruction::Jump { address: 0x202 },
+        ].into_iter().flat_map(Instruction::encode_bytes).collect();
+
+        let mut recorded = Emulator::new(Settings::default(), program.clone()).unwrap();
+        let mut movie = Movie::new("abc", recorded.settings(), recorded.state().random().seed());
+        for frame in 0..120 {
+            let mut input = EmulatorInput::new();
+            if frame % 20 < 10 {
+                input.keypad.set_key_pressed(0x5);
+            }
+            movie.record(frame, &input.keypad);
+            recorded.run_frame(input);
+        }
+
+        let play = |movie: &Movie| {
+            let mut emulator = Emulator::new(movie.settings(), program.clone()).unwrap();
+            for frame in 0..movie.len() {
+                emulator.run_frame(EmulatorInput { quit: false, keypad: movie.keypad(frame).unwrap() });
+            }
+            emulator
+        };
+        let played = play(&Movie::load(&movie.save()).unwrap());
+        // The recorded settings only differ by the seed being fixed, so everything but the settings must match.
+        let (played, recorded) = (played.state(), recorded.state());
+        assert_eq!(played.memory(), recorded.memory());
+        let registers = |state: &State| (0..16).map(|register| state.register(register)).collect::<Vec<u8>>();
+        assert_eq!(registers(played), registers(recorded));
+        assert_eq!((played.program_counter, played.address_register()), (recorded.program_counter, recorded.address_register()));
+        assert_eq!(played.random(), recorded.random());
+        assert_eq!(played.display.visible_pixels(), recorded.display.visible_pixels());
+
+        let mut without_input = movie.clone();
+        for frame in 0..movie.len() {
+            without_input.record(frame, &Keypad::new());
+        }
+        assert_ne!(play(&without_input).display().visible_pixels(), recorded.display.visible_pixels());
+    }
+}
@@ -0,0 +1,73 @@
+use chip8::{Emulator, Keypad, Movie, Settings};
+use crate::rom::Rom;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+enum Mode {
+    /// Recording into the movie, which is written to the path when finished.
+    Recording(PathBuf),
+    Playing,
+}
+
+/// Records the input of a run into a movie, or plays a movie back in place of the frontend's input.
+pub struct MovieSession {
+    movie: Movie,
+    mode: Mode,
+}
+
+impl MovieSession {
+    /// Starts recording a run of the emulator, which must not have run any frames yet.
+    pub fn record(path: &Path, emulator: &Emulator, rom: &Rom) -> Self {
+        let movie = Movie::new(&rom.sha1, emulator.settings(), emulator.state().random().seed());
+        Self { movie, mode: Mode::Recording(path.to_path_buf()) }
+    }
+
+    /// Loads a movie for playback, checking that it was recorded with the given ROM.
+    pub fn play(path: &Path, rom: &Rom) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|error| format!("Could not read movie {}: {}.", path.display(), error))?;
+        let movie = Movie::load(&data).map_err(|error| format!("Could not load movie {}: {}", path.display(), error))?;
+        if movie.rom_sha1() != rom.sha1 {
+            return Err(format!("Movie {} was recorded with a different ROM (SHA-1: {}).", path.display(), movie.rom_sha1()));
+        }
+        Ok(Self { movie, mode: Mode::Playing })
+    }
+
+    /// The settings the emulator must use to reproduce the movie.
+    pub fn settings(&self) -> Settings {
+        self.movie.settings()
+    }
+
+    /// The number of frames to play back, or `None` when recording.
+    pub fn playback_frames(&self) -> Option<usize> {
+        match self.mode {
+            Mode::Recording(_) => None,
+            Mode::Playing => Some(self.movie.len()),
+        }
+    }
+
+    /// Records the input of the given frame, counted from power-on, or replaces it with the recorded input when
+    /// playing. Once playback has reached the end of the movie, the input is left as it is. After rewinding, frames
+    /// are recorded over and played back again from the frame the emulator was rewound to.
+    pub fn next_frame(&mut self, frame: usize, keypad: &mut Keypad) {
+        match self.mode {
+            Mode::Recording(_) => self.movie.record(frame, keypad),
+            Mode::Playing => match self.movie.keypad(frame) {
+                Some(recorded_keypad) => *keypad = recorded_keypad,
+                None if frame == self.movie.len() => eprintln!("Movie ended after {} frames.", frame),
+                None => {},
+            },
+        }
+    }
+
+    /// Writes the recorded movie, yielding its path, or does nothing when playing. Frames that were rewound but not
+    /// recorded over yet are kept, as they still replay the run from before rewinding.
+    pub fn finish(self) -> Result<Option<PathBuf>, String> {
+        match &self.mode {
+            Mode::Recording(path) => {
+                fs::write(path, self.movie.save()).map_err(|error| format!("Could not write movie {}: {}.", path.display(), error))?;
+                Ok(Some(path.clone()))
+            },
+            Mode::Playing => Ok(None),
+        }
+    }
+}
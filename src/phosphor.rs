@@ -0,0 +1,160 @@
+use chip8::{DirtyRows, Display, EmulatorOutput};
+use crate::config::Persistence;
+
+const LIT: u8 = u8::MAX;
+
+/// A render stage that lets erased pixels linger, as on the phosphor screens CHIP-8 games were written for, which hides
+/// the flicker of sprites being erased and redrawn.
+pub struct Phosphor {
+    persistence: Persistence,
+    /// The brightness lost by an erased pixel every frame, when pixels decay.
+    decay_step: u8,
+    width: u8,
+    height: u8,
+    /// The brightness of each pixel, row by row, from 0 for dark to 255 for lit.
+    levels: Vec<u8>,
+    /// Whether each pixel was lit in the previous frame.
+    previous: Vec<bool>,
+    /// The version of the display at the last update.
+    display_version: Option<u64>,
+    /// The rows with erased pixels that are still lingering.
+    lingering: DirtyRows,
+}
+
+impl Phosphor {
+    pub fn new(persistence: Persistence, decay_frames: u8) -> Self {
+        Self {
+            persistence,
+            decay_step: LIT.div_ceil(decay_frames.max(1)),
+            width: 0,
+            height: 0,
+            levels: vec![],
+            previous: vec![],
+            display_version: None,
+            lingering: DirtyRows::default(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.persistence != Persistence::Off
+    }
+
+    /// Advances to the next frame of the display, yielding the rows to redraw: those that changed on the display, and
+    /// those in which the brightness of any pixel changed. Only these rows and the rows still lingering are visited.
+    /// When the display resolution changes, pixels are shown as they are, without lingering.
+    pub fn update(&mut self, display: &Display, output: &EmulatorOutput) -> DirtyRows {
+        let resized = (self.width, self.height) != (display.width, display.height);
+        if resized {
+            self.width = display.width;
+            self.height = display.height;
+            self.levels = vec![0; display.width as usize * display.height as usize];
+            self.previous = vec![false; self.levels.len()];
+            self.lingering = DirtyRows::default();
+        }
+        // The rows changed in the last frame only suffice when the last update saw the display at its start.
+        let mut rows = match self.display_version {
+            Some(version) if version >= output.dirty_since_version && !resized => output.dirty_rows,
+            _ => DirtyRows::all(display.height),
+        };
+        self.display_version = Some(output.display_version);
+
+        let mut visited = rows;
+        visited.extend(std::mem::take(&mut self.lingering));
+        for y in visited.iter() {
+            for x in 0..display.width {
+                let index = y as usize * display.width as usize + x as usize;
+                let lit = display.pixel(x, y) != 0;
+                let level = match self.persistence {
+                    _ if lit => LIT,
+                    Persistence::Blend if self.previous[index] && !resized => LIT,
+                    Persistence::Decay if !resized => self.levels[index].saturating_sub(self.decay_step),
+                    _ => 0,
+                };
+                if level != self.levels[index] {
+                    rows.insert(y);
+                }
+                if level > 0 && !lit {
+                    self.lingering.insert(y);
+                }
+                self.levels[index] = level;
+                self.previous[index] = lit;
+            }
+        }
+        rows
+    }
+
+    /// The brightness of the pixel as of the last update, from 0 for dark to 255 for lit.
+    pub fn level(&self, x: u8, y: u8) -> u8 {
+        if x < self.width && y < self.height {
+            self.levels[y as usize * self.width as usize + x as usize]
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(lit: bool) -> Display {
+        let mut display = Display::new(8, 2, false);
+        if lit {
+            display.apply_sprite(0, 0, &[0x80]);
+        }
+        display
+    }
+
+    /// The output of a frame that left the display at the given version, which changed the first row if `changed`.
+    fn output(display_version: u64, changed: bool) -> EmulatorOutput {
+        EmulatorOutput {
+            display_version,
+            dirty_rows: if changed { DirtyRows::all(1) } else { DirtyRows::default() },
+            dirty_since_version: if changed { display_version - 1 } else { display_version },
+            sound_playing: false,
+            audio_pattern: None,
+            halted: false,
+            fault: None,
+        }
+    }
+
+    fn rows(rows: DirtyRows) -> Vec<u8> {
+        rows.iter().collect()
+    }
+
+    #[test]
+    fn test_blend() {
+        let mut phosphor = Phosphor::new(Persistence::Blend, 4);
+        assert_eq!(rows(phosphor.update(&display(true), &output(1, true))), vec![0, 1]);
+        assert_eq!(phosphor.level(0, 0), LIT);
+        assert_eq!(rows(phosphor.update(&display(false), &output(2, true))), vec![0]);
+        assert_eq!(phosphor.level(0, 0), LIT);
+        assert_eq!(rows(phosphor.update(&display(false), &output(2, false))), vec![0]);
+        assert_eq!(phosphor.level(0, 0), 0);
+        assert!(phosphor.update(&display(false), &output(2, false)).is_empty());
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut phosphor = Phosphor::new(Persistence::Decay, 4);
+        phosphor.update(&display(true), &output(1, true));
+        let levels: Vec<u8> = (0..4).map(|frame| {
+            assert_eq!(rows(phosphor.update(&display(false), &output(2, frame == 0))), vec![0]);
+            phosphor.level(0, 0)
+        }).collect();
+        assert_eq!(levels, vec![191, 127, 63, 0]);
+        assert!(phosphor.update(&display(false), &output(2, false)).is_empty());
+        phosphor.update(&display(true), &output(3, true));
+        assert_eq!(phosphor.level(0, 0), LIT);
+    }
+
+    #[test]
+    fn test_missed_frames_update_all_rows() {
+        let mut phosphor = Phosphor::new(Persistence::Decay, 4);
+        phosphor.update(&display(false), &output(1, true));
+        let mut skipped = output(3, true);
+        skipped.dirty_rows = DirtyRows::default();
+        assert_eq!(rows(phosphor.update(&display(true), &skipped)), vec![0, 1]);
+        assert_eq!(phosphor.level(0, 0), LIT);
+    }
+}
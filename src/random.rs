@@ -0,0 +1,64 @@
+use crate::savestate::{Reader, SaveStateError, Writer};
+
+/// A deterministic xorshift64* random number generator, so that runs with the same seed and input are reproducible.
+/// The COSMAC VIP interpreter's own generator is not emulated, as it mixes in bytes of the interpreter code, which is
+/// not part of the emulated memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Random {
+    seed: u32,
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        // SplitMix64 spreads the seed over the state, which xorshift requires to be non-zero.
+        let mut state = (seed as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self {
+            seed,
+            state: (state ^ (state >> 31)).max(1),
+        }
+    }
+
+    /// The seed the generator was created with.
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    pub(crate) fn save_to(&self, writer: &mut Writer) {
+        writer.u32(self.seed);
+        writer.u64(self.state);
+    }
+
+    pub(crate) fn load_from(reader: &mut Reader) -> Result<Self, SaveStateError> {
+        let seed = reader.u32()?;
+        let state = reader.u64()?;
+        if state == 0 {
+            return Err(SaveStateError::InvalidValue("random state"));
+        }
+        Ok(Self { seed, state })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let sequence = |seed| {
+            let mut random = Random::new(seed);
+            (0..16).map(|_| random.next_byte()).collect::<Vec<u8>>()
+        };
+        assert_eq!(sequence(42), sequence(42));
+        assert_ne!(sequence(42), sequence(43));
+    }
+}
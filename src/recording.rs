@@ -0,0 +1,357 @@
+use chip8::Display;
+use crate::config::{Config, Rgb};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// GIF viewers show frames with delays below 2 centiseconds for much longer, so shorter frames are merged.
+const MIN_GIF_DELAY: u64 = 2;
+
+const MICROS_PER_SECOND: u64 = 1_000_000;
+const MICROS_PER_CENTISECOND: u64 = 10_000;
+
+/// The emulated time at which frames start, in microseconds, following changes of the frame rate.
+struct FrameClock {
+    frame_rate: u64,
+    /// The number of frames since the frame rate last changed.
+    frames: u64,
+    /// The time at which the frame rate last changed.
+    base: u64,
+}
+
+impl FrameClock {
+    fn new(frame_rate: u16) -> Self {
+        Self { frame_rate: frame_rate as u64, frames: 0, base: 0 }
+    }
+
+    /// The start of the next frame, which is the end of the last one.
+    fn now(&self) -> u64 {
+        self.base + self.frames * MICROS_PER_SECOND / self.frame_rate
+    }
+
+    fn tick(&mut self) {
+        self.frames += 1;
+    }
+
+    fn set_frame_rate(&mut self, frame_rate: u16) {
+        if frame_rate as u64 != self.frame_rate {
+            self.base = self.now();
+            self.frames = 0;
+            self.frame_rate = frame_rate as u64;
+        }
+    }
+}
+
+/// The size of recorded frames, which fits the high resolution display at the configured scale, so that switching
+/// resolutions does not change it.
+#[derive(Clone, Copy)]
+struct FrameSize {
+    width: u32,
+    height: u32,
+}
+
+impl FrameSize {
+    /// The palette index of every pixel of the frame, by sampling the display.
+    fn sample(self, display: &Display) -> Vec<u8> {
+        let mut indices = Vec::with_capacity((self.width * self.height) as usize);
+        for y in 0..self.height {
+            let display_y = (y * display.height as u32 / self.height) as u8;
+            for x in 0..self.width {
+                let display_x = (x * display.width as u32 / self.width) as u8;
+                indices.push(display.pixel(display_x, display_y).min(3));
+            }
+        }
+        indices
+    }
+}
+
+/// Records the display into an animated GIF, timed by the emulated frame rate rather than wall-clock time.
+struct GifRecorder {
+    path: PathBuf,
+    encoder: gif::Encoder<BufWriter<File>>,
+    /// The emulated time at which the recording started, in microseconds.
+    start: u64,
+    /// The last frame, which is only written once the next different frame shows how long it lasted, along with its start time in centiseconds.
+    pending: Option<(Vec<u8>, u64)>,
+}
+
+impl GifRecorder {
+    fn new(path: &Path, size: FrameSize, palette: &[Rgb; 4], start: u64) -> Result<Self, String> {
+        let palette: Vec<u8> = palette.iter().flat_map(|&Rgb(red, green, blue)| [red, green, blue]).collect();
+        let create = || -> Result<gif::Encoder<BufWriter<File>>, Box<dyn Error>> {
+            let mut encoder = gif::Encoder::new(BufWriter::new(File::create(path)?), size.width as u16, size.height as u16, &palette)?;
+            encoder.set_repeat(gif::Repeat::Infinite)?;
+            Ok(encoder)
+        };
+        let encoder = create().map_err(|error| format!("Could not record {}: {}.", path.display(), error))?;
+        Ok(Self { path: path.to_path_buf(), encoder, start, pending: None })
+    }
+
+    /// Adds a frame starting at the given emulated time, in microseconds.
+    fn record(&mut self, size: FrameSize, indices: Vec<u8>, start: u64) -> Result<(), String> {
+        let time = (start - self.start) / MICROS_PER_CENTISECOND;
+        match &self.pending {
+            Some((pending, _)) if *pending == indices => {},
+            Some((_, start)) if time - start < MIN_GIF_DELAY => {
+                let start = *start;
+                self.pending = Some((indices, start));
+            },
+            _ => {
+                self.write_pending(size, time)?;
+                self.pending = Some((indices, time));
+            },
+        }
+        Ok(())
+    }
+
+    fn write_pending(&mut self, size: FrameSize, end: u64) -> Result<(), String> {
+        if let Some((indices, start)) = self.pending.take() {
+            let frame = gif::Frame {
+                width: size.width as u16,
+                height: size.height as u16,
+                delay: (end - start).clamp(MIN_GIF_DELAY, u16::MAX as u64) as u16,
+                buffer: Cow::Owned(indices),
+                ..gif::Frame::default()
+            };
+            self.encoder.write_frame(&frame).map_err(|error| format!("Could not record {}: {}.", self.path.display(), error))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last frame, which lasts until the given emulated time, in microseconds.
+    fn finish(mut self, size: FrameSize, end: u64) -> Result<PathBuf, String> {
+        let end = (end - self.start).div_ceil(MICROS_PER_CENTISECOND);
+        self.write_pending(size, end)?;
+        let path = self.path.clone();
+        let writer = self.encoder.into_inner().map_err(|error| format!("Could not record {}: {}.", path.display(), error))?;
+        writer.into_inner().map_err(|error| format!("Could not record {}: {}.", path.display(), error.error()))?;
+        Ok(path)
+    }
+}
+
+/// Streams the display as uncompressed YUV 4:4:4 video in the YUV4MPEG2 format. The video has the frame rate the
+/// recording started with, and frames are repeated or dropped to keep in time if the emulated frame rate changes.
+struct Y4mRecorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    /// The Y, Cb and Cr components of each palette entry.
+    palette: [[u8; 3]; 4],
+    frame_rate: u64,
+    /// The number of video frames written.
+    frames: u64,
+}
+
+impl Y4mRecorder {
+    fn new(path: &Path, size: FrameSize, palette: &[Rgb; 4], frame_rate: u16) -> Result<Self, String> {
+        let file = File::create(path).map_err(|error| format!("Could not record {}: {}.", path.display(), error))?;
+        let writer = BufWriter::new(file);
+        let mut recorder = Self { path: path.to_path_buf(), writer, palette: palette.map(ycbcr), frame_rate: frame_rate as u64, frames: 0 };
+        let header = format!("YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444\n", size.width, size.height, frame_rate);
+        recorder.write(header.as_bytes())?;
+        Ok(recorder)
+    }
+
+    /// Adds a frame lasting until the given emulated time, in microseconds, writing it once for every video frame
+    /// starting before then.
+    fn record(&mut self, indices: &[u8], end: u64) -> Result<(), String> {
+        let mut frame = Vec::with_capacity(6 + indices.len() * 3);
+        frame.extend_from_slice(b"FRAME\n");
+        for component in 0..3 {
+            frame.extend(indices.iter().map(|&index| self.palette[index as usize][component]));
+        }
+        while self.frames * MICROS_PER_SECOND / self.frame_rate < end {
+            self.write(&frame)?;
+            self.frames += 1;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.writer.write_all(data).map_err(|error| format!("Could not record {}: {}.", self.path.display(), error))
+    }
+
+    fn finish(mut self) -> Result<PathBuf, String> {
+        self.writer.flush().map_err(|error| format!("Could not record {}: {}.", self.path.display(), error))?;
+        Ok(self.path)
+    }
+}
+
+/// Converts a color to studio range BT.601 Y, Cb and Cr components.
+fn ycbcr(Rgb(red, green, blue): Rgb) -> [u8; 3] {
+    let (red, green, blue) = (red as f64, green as f64, blue as f64);
+    let y = 16.0 + (65.481 * red + 128.553 * green + 24.966 * blue) / 255.0;
+    let cb = 128.0 + (-37.797 * red - 74.203 * green + 112.0 * blue) / 255.0;
+    let cr = 128.0 + (112.0 * red - 93.786 * green - 18.214 * blue) / 255.0;
+    [y.round() as u8, cb.round() as u8, cr.round() as u8]
+}
+
+/// Records every emulated frame to the active GIF and Y4M recordings.
+pub struct Recorder {
+    size: FrameSize,
+    palette: [Rgb; 4],
+    clock: FrameClock,
+    gif: Option<GifRecorder>,
+    y4m: Option<Y4mRecorder>,
+}
+
+impl Recorder {
+    /// Creates a recorder, starting the given recordings right away.
+    pub fn new(config: &Config, gif_path: Option<&Path>, y4m_path: Option<&Path>) -> Result<Self, String> {
+        let settings = &config.settings;
+        let size = FrameSize {
+            width: settings.display_width as u32 * 2 * config.screenshot_scale,
+            height: settings.display_height as u32 * 2 * config.screenshot_scale,
+        };
+        if size.width > u16::MAX as u32 || size.height > u16::MAX as u32 {
+            return Err("Recordings are limited to 65535 pixels in each dimension. Please lower the screenshot scale.".to_string());
+        }
+        let palette = config.colors.palette();
+        let mut recorder = Self { size, palette, clock: FrameClock::new(settings.frame_rate), gif: None, y4m: None };
+        if let Some(path) = gif_path {
+            recorder.start_gif(path)?;
+        }
+        if let Some(path) = y4m_path {
+            recorder.y4m = Some(Y4mRecorder::new(path, size, &palette, settings.frame_rate)?);
+        }
+        Ok(recorder)
+    }
+
+    /// Starts a GIF recording, replacing the active one, if any, without finishing it.
+    pub fn start_gif(&mut self, path: &Path) -> Result<(), String> {
+        self.gif = Some(GifRecorder::new(path, self.size, &self.palette, self.clock.now())?);
+        Ok(())
+    }
+
+    /// Finishes the GIF recording, if any, yielding its path.
+    pub fn stop_gif(&mut self) -> Result<Option<PathBuf>, String> {
+        let end = self.clock.now();
+        self.gif.take().map(|gif| gif.finish(self.size, end)).transpose()
+    }
+
+    /// Adds a frame lasting for the given frame rate, which changes when loading a state saved with a different one, to
+    /// the active recordings. A recording that cannot be written is stopped, and the others go on.
+    pub fn record(&mut self, display: &Display, frame_rate: u16) -> Result<(), String> {
+        self.clock.set_frame_rate(frame_rate);
+        let start = self.clock.now();
+        self.clock.tick();
+        if self.gif.is_none() && self.y4m.is_none() {
+            return Ok(());
+        }
+        let indices = self.size.sample(display);
+        let mut result = Ok(());
+        if let Some(Err(error)) = self.y4m.as_mut().map(|y4m| y4m.record(&indices, self.clock.now())) {
+            self.y4m = None;
+            result = Err(format!("{} Stopped recording.", error));
+        }
+        if let Some(Err(error)) = self.gif.as_mut().map(|gif| gif.record(self.size, indices, start)) {
+            self.gif = None;
+            result = Err(format!("{} Stopped recording.", error));
+        }
+        result
+    }
+
+    /// Finishes all recordings, yielding the paths of the recorded files.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>, String> {
+        let mut paths: Vec<PathBuf> = self.stop_gif()?.into_iter().collect();
+        paths.extend(self.y4m.take().map(Y4mRecorder::finish).transpose()?);
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_display() {
+        let mut display = Display::new(64, 32, false);
+        display.apply_sprite(1, 0, &[0x80]);
+        let size = FrameSize { width: 256, height: 128 };
+        let indices = size.sample(&display);
+        assert_eq!(indices.len(), 256 * 128);
+        assert_eq!(&indices[0 .. 9], &[0, 0, 0, 0, 1, 1, 1, 1, 0]);
+        assert_eq!(indices[3 * 256 + 4], 1);
+        assert_eq!(indices[4 * 256 + 4], 0);
+    }
+
+    #[test]
+    fn test_ycbcr() {
+        assert_eq!(ycbcr(Rgb(0, 0, 0)), [16, 128, 128]);
+        assert_eq!(ycbcr(Rgb(255, 255, 255)), [235, 128, 128]);
+    }
+
+    fn alternating_displays() -> [Display; 2] {
+        let mut lit = Display::new(64, 32, false);
+        lit.apply_sprite(0, 0, &[0x80]);
+        [Display::new(64, 32, false), lit]
+    }
+
+    /// The delays of the frames of the GIF at the path, which is removed.
+    fn gif_delays(path: &Path) -> Vec<u16> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(path).unwrap()).unwrap();
+        let mut delays = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        std::fs::remove_file(path).unwrap();
+        delays
+    }
+
+    #[test]
+    fn test_gif_delays() {
+        let path = std::env::temp_dir().join(format!("chip8-recording-{}.gif", std::process::id()));
+        let mut config = crate::config::Config::resolve(&[]).unwrap();
+        config.screenshot_scale = 1;
+
+        // At 30 Hz, frames last 3 1/3 centiseconds, so every third frame is a centisecond longer.
+        config.settings.frame_rate = 30;
+        let mut recorder = Recorder::new(&config, Some(&path), None).unwrap();
+        for display in alternating_displays().iter().cycle().take(6) {
+            recorder.record(display, 30).unwrap();
+        }
+        recorder.finish().unwrap();
+        assert_eq!(gif_delays(&path), vec![3, 3, 4, 3, 3, 4]);
+
+        // Frames shorter than the minimum delay are replaced by the next one, so the first frame is dropped and the last
+        // dark frame is replaced by the lit frame at 20 Hz, which starts at 3 centiseconds and ends at 4 + 5.
+        config.settings.frame_rate = 100;
+        let mut recorder = Recorder::new(&config, Some(&path), None).unwrap();
+        let [dark, lit] = alternating_displays();
+        for display in [&dark, &lit, &lit, &dark] {
+            recorder.record(display, 100).unwrap();
+        }
+        recorder.record(&lit, 20).unwrap();
+        recorder.finish().unwrap();
+        assert_eq!(gif_delays(&path), vec![3, 6]);
+    }
+
+    #[test]
+    fn test_y4m_recording() {
+        let path = std::env::temp_dir().join(format!("chip8-recording-{}.y4m", std::process::id()));
+        let mut config = crate::config::Config::resolve(&[]).unwrap();
+        config.screenshot_scale = 1;
+        let mut recorder = Recorder::new(&config, None, Some(&path)).unwrap();
+        let display = Display::new(64, 32, false);
+        recorder.record(&display, 60).unwrap();
+        recorder.record(&display, 60).unwrap();
+        assert_eq!(recorder.finish().unwrap(), vec![path.clone()]);
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+        assert!(data.starts_with(header));
+        assert_eq!(data.len(), header.len() + 2 * (6 + 128 * 64 * 3));
+
+        // Frames are repeated to keep the video in time when the frame rate drops.
+        let mut recorder = Recorder::new(&config, None, Some(&path)).unwrap();
+        recorder.record(&display, 20).unwrap();
+        recorder.finish().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data.len(), header.len() + 3 * (6 + 128 * 64 * 3));
+    }
+}
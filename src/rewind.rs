@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use crate::state::State;
+
+/// The bytes that differ between two save state encodings, which is usually a small part of memory and the registers.
+struct Delta {
+    length: usize,
+    /// The runs of bytes to write, by their offset.
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    /// The delta that turns `from` into `to`.
+    fn between(from: &[u8], to: &[u8]) -> Self {
+        let mut runs: Vec<(usize, Vec<u8>)> = vec![];
+        for (offset, &byte) in to.iter().enumerate() {
+            if from.get(offset) == Some(&byte) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() == offset => bytes.push(byte),
+                _ => runs.push((offset, vec![byte])),
+            }
+        }
+        Self { length: to.len(), runs }
+    }
+
+    fn apply(&self, data: &mut Vec<u8>) {
+        data.resize(self.length, 0);
+        for (offset, bytes) in &self.runs {
+            data[*offset .. *offset + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+}
+
+/// The states at the start of recent frames. Only the latest state is kept in full, as a save state encoding, and each
+/// earlier one as the delta from the state after it, which keeps long buffers small even with 64 KiB of memory.
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    /// The deltas to earlier states, oldest first.
+    deltas: VecDeque<Delta>,
+}
+
+impl RewindBuffer {
+    pub fn new() -> Self {
+        Self {
+            capacity: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Sets the number of states kept, dropping the oldest ones beyond it. A capacity of 0 disables rewinding.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        if capacity == 0 {
+            self.clear();
+        }
+        self.drop_oldest();
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    pub fn push(&mut self, state: &State) {
+        if self.capacity == 0 {
+            return;
+        }
+        let data = state.save();
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(Delta::between(&data, &latest));
+            self.drop_oldest();
+        }
+        self.latest = Some(data);
+    }
+
+    /// Removes the latest state, or yields `None` if there are none left.
+    pub fn pop(&mut self) -> Option<State> {
+        let mut data = self.latest.take()?;
+        let state = State::load(&data).expect("Could not restore a rewound state.");
+        if let Some(delta) = self.deltas.pop_back() {
+            delta.apply(&mut data);
+            self.latest = Some(data);
+        }
+        Some(state)
+    }
+
+    /// Drops the oldest states beyond the capacity, which includes the latest state.
+    fn drop_oldest(&mut self) {
+        while self.deltas.len() >= self.capacity.max(1) {
+            self.deltas.pop_front();
+        }
+    }
+}
+
+impl Default for RewindBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    #[test]
+    fn test_push_and_pop() {
+        let mut buffer = RewindBuffer::new();
+        buffer.set_capacity(3);
+        let mut state = State::new(Settings::default(), vec![0x12, 0x34]).unwrap();
+        let mut states = vec![];
+        for value in 0..5 {
+            state.set_register(0x0, value);
+            state.write_memory(0x300 + value as u16, &[value]).unwrap();
+            if value == 3 {
+                state.display.set_high_resolution(true);
+            }
+            buffer.push(&state);
+            states.push(state.save());
+        }
+        assert_eq!(buffer.len(), 3);
+
+        for expected in states.iter().rev().take(3) {
+            assert_eq!(&buffer.pop().unwrap().save(), expected);
+        }
+        assert!(buffer.pop().is_none());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_shrink_capacity() {
+        let mut buffer = RewindBuffer::new();
+        buffer.set_capacity(4);
+        let mut state = State::new(Settings::default(), vec![]).unwrap();
+        for value in 0..4 {
+            state.set_register(0x0, value);
+            buffer.push(&state);
+        }
+        buffer.set_capacity(2);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.pop().unwrap().register(0x0), 3);
+        assert_eq!(buffer.pop().unwrap().register(0x0), 2);
+        assert!(buffer.pop().is_none());
+    }
+}
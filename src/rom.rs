@@ -0,0 +1,36 @@
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::path::Path;
+
+pub struct Rom {
+    /// The file name of the ROM, without any directories.
+    pub name: String,
+    /// The lowercase hexadecimal SHA-1 hash of the ROM data.
+    pub sha1: String,
+    pub data: Vec<u8>,
+}
+
+impl Rom {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|error| format!("Could not load program {}: {}.", path, error))?;
+        let name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string());
+        Ok(Self { name, sha1: sha1_hex(&data), data })
+    }
+}
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    Sha1::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha1_hex() {
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+}
@@ -0,0 +1,208 @@
+use crate::config::{ColorsProfile, Profile};
+use chip8::settings::Platform;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+// A snapshot of the CHIP-8 community program database (https://github.com/chip-8/chip-8-database), see its README.md.
+const PROGRAMS: &str = include_str!("../data/chip-8-database/programs.json");
+const HASHES: &str = include_str!("../data/chip-8-database/sha1-hashes.json");
+const PLATFORMS: &str = include_str!("../data/chip-8-database/platforms.json");
+
+#[derive(Deserialize)]
+struct ProgramEntry {
+    title: String,
+    roms: HashMap<String, RomEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RomEntry {
+    platforms: Vec<String>,
+    quirky_platforms: Option<HashMap<String, HashMap<String, bool>>>,
+    tickrate: Option<u32>,
+    start_address: Option<u16>,
+    keys: Option<BTreeMap<String, u8>>,
+    colors: Option<ColorsEntry>,
+}
+
+#[derive(Deserialize)]
+struct ColorsEntry {
+    pixels: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlatformEntry {
+    id: String,
+    name: String,
+    default_tickrate: u32,
+    quirks: HashMap<String, bool>,
+}
+
+/// The bundled database, parsed on first use.
+struct Database {
+    hashes: HashMap<String, usize>,
+    programs: Vec<ProgramEntry>,
+    platforms: Vec<PlatformEntry>,
+}
+
+impl Database {
+    fn get() -> &'static Database {
+        static DATABASE: OnceLock<Database> = OnceLock::new();
+        DATABASE.get_or_init(|| Database {
+            hashes: serde_json::from_str(HASHES).expect("Could not parse bundled ROM hashes."),
+            programs: serde_json::from_str(PROGRAMS).expect("Could not parse bundled ROM database."),
+            platforms: serde_json::from_str(PLATFORMS).expect("Could not parse bundled platform database."),
+        })
+    }
+}
+
+/// A ROM identified in the program database, along with the configuration it needs.
+pub struct Detection {
+    pub title: String,
+    pub platform_name: String,
+    pub tickrate: u32,
+    /// Keys used by the ROM, by their purpose (e.g. "up" or "a").
+    pub key_hints: BTreeMap<String, u8>,
+    pub profile: Profile,
+}
+
+impl Detection {
+    pub fn describe(&self) -> String {
+        let mut description = format!("Detected {} ({}, {} cycles per frame).", self.title, self.platform_name, self.tickrate);
+        if !self.key_hints.is_empty() {
+            let hints: Vec<String> = self.key_hints.iter().map(|(purpose, key)| format!("{}: {:X}", purpose, key)).collect();
+            description.push_str(&format!(" Keys: {}.", hints.join(", ")));
+        }
+        description
+    }
+
+    /// The detected configuration, to be applied before the given profiles. When one of those picks the platform, only
+    /// the start address and colors are kept, as the quirks and clock speed are those of the detected platform.
+    pub fn profile_before(&self, later_profiles: &[&Profile]) -> Profile {
+        if later_profiles.iter().any(|profile| profile.platform.is_some()) {
+            Profile {
+                program_start_address: self.profile.program_start_address,
+                colors: self.profile.colors.clone(),
+                ..Profile::default()
+            }
+        } else {
+            self.profile.clone()
+        }
+    }
+}
+
+/// Looks up a ROM by its SHA-1 hash in the bundled program database. The database gives speeds in cycles per frame,
+/// which are converted to a clock speed at the given frame rate.
+pub fn lookup(sha1: &str, frame_rate: u16) -> Option<Detection> {
+    let database = Database::get();
+    let program = database.programs.get(*database.hashes.get(sha1)?)?;
+    let platforms = &database.platforms;
+    let rom = program.roms.get(sha1)?;
+    let quirky_platforms = rom.quirky_platforms.clone().unwrap_or_default();
+
+    // ROMs list their platforms in order of preference, while platforms with quirks are only listed separately.
+    let (platform, preset) = rom.platforms
+        .iter()
+        .chain(quirky_platforms.keys())
+        .filter_map(|id| Some((platforms.iter().find(|platform| &platform.id == id)?, preset(id)?)))
+        .next()?;
+
+    let mut quirks = platform.quirks.clone();
+    quirks.extend(quirky_platforms.get(&platform.id).cloned().unwrap_or_default());
+
+    let tickrate = rom.tickrate.unwrap_or(platform.default_tickrate);
+    let clock_speed = (tickrate * frame_rate as u32).min(u16::MAX as u32) as u16;
+
+    let pixels = rom.colors.as_ref().and_then(|colors| colors.pixels.clone()).unwrap_or_default();
+    let colors = (pixels.len() >= 2).then(|| ColorsProfile {
+        background: Some(pixels[0].clone()),
+        foreground: Some(pixels[1].clone()),
+        foreground2: pixels.get(2).cloned(),
+        blend: pixels.get(3).cloned(),
+    });
+
+    let profile = Profile {
+        platform: Some(preset.to_string()),
+        clock_speed: Some(clock_speed),
+        program_start_address: rom.start_address,
+        in_place_shift: quirks.get("shift").copied(),
+        flexible_jump_offset: quirks.get("jump").copied(),
+        auto_address_increments: quirks.get("memoryLeaveIUnchanged").map(|unchanged| !unchanged),
+        flag_reset_on_logic_ops: quirks.get("logic").copied(),
+        sprite_wrapping: quirks.get("wrap").copied(),
+        sprite_draw_delay: quirks.get("vblank").copied(),
+        colors,
+        ..Profile::default()
+    };
+
+    Some(Detection {
+        title: program.title.clone(),
+        platform_name: platform.name.clone(),
+        tickrate,
+        key_hints: rom.keys.clone().unwrap_or_default(),
+        profile,
+    })
+}
+
+/// The closest supported platform preset for a platform in the database.
+fn preset(id: &str) -> Option<Platform> {
+    match id {
+        "originalChip8" | "hybridVIP" | "modernChip8" => Some(Platform::CosmacVip),
+        "chip48" => Some(Platform::Chip48),
+        "superchip1" | "superchip" => Some(Platform::SuperChip11),
+        "xochip" => Some(Platform::XoChip),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Rgb};
+    use chip8::Settings;
+
+    #[test]
+    fn test_lookup() {
+        let detection = lookup("06a6692c92eb8077329b6d4e59d55479d60574a8", 60).unwrap();
+        assert_eq!(detection.title, "Snake");
+        assert_eq!(detection.tickrate, 15);
+        assert_eq!(detection.key_hints.get("up"), Some(&5));
+        assert_eq!(detection.profile.platform.as_deref(), Some("super-chip"));
+        assert_eq!(detection.profile.clock_speed, Some(15 * 60));
+        assert_eq!(detection.profile.in_place_shift, Some(true));
+        assert_eq!(detection.profile.auto_address_increments, Some(false));
+        assert_eq!(detection.profile.colors.unwrap().background.as_deref(), Some("#30283e"));
+
+        let detection = lookup("06a6692c92eb8077329b6d4e59d55479d60574a8", 50).unwrap();
+        assert_eq!(detection.profile.clock_speed, Some(15 * 50));
+    }
+
+    #[test]
+    fn test_lookup_quirky_platform() {
+        let detection = lookup("ac621d9fcada302ba6965768229ef130630bc525", 60).unwrap();
+        assert_eq!(detection.profile.platform.as_deref(), Some("cosmac-vip"));
+        assert_eq!(detection.profile.auto_address_increments, Some(false));
+        assert_eq!(detection.profile.flag_reset_on_logic_ops, Some(true));
+    }
+
+    #[test]
+    fn test_platform_overrides_detection() {
+        let detection = lookup("06a6692c92eb8077329b6d4e59d55479d60574a8", 60).unwrap();
+        let options = Profile { platform: Some("cosmac-vip".to_string()), ..Profile::default() };
+        let config = Config::resolve(&[&detection.profile_before(&[&options]), &options]).unwrap();
+        assert_eq!(config.platform, Some(Platform::CosmacVip));
+        assert_eq!(config.settings, Settings::preset(Platform::CosmacVip));
+        assert_eq!(config.colors.background, Rgb(0x30, 0x28, 0x3e));
+
+        let config = Config::resolve(&[&detection.profile_before(&[]), &Profile::default()]).unwrap();
+        assert_eq!(config.platform, Some(Platform::SuperChip11));
+        assert_eq!(config.settings.clock_speed, 15 * 60);
+    }
+
+    #[test]
+    fn test_lookup_unknown() {
+        assert!(lookup("0000000000000000000000000000000000000000", 60).is_none());
+    }
+}
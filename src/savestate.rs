@@ -0,0 +1,159 @@
+use std::fmt;
+
+const MAGIC: &[u8; 4] = b"CH8S";
+pub const FORMAT_VERSION: u16 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveStateError {
+    /// The data does not start with the save state magic bytes.
+    InvalidHeader,
+    /// The save state was written in a format version this build cannot read.
+    UnsupportedVersion(u16),
+    /// The data ended before the save state was complete.
+    Truncated,
+    /// The save state contains a value that is out of range.
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::InvalidHeader => write!(f, "Not a save state."),
+            SaveStateError::UnsupportedVersion(version) => write!(f, "Unsupported save state version: {}.", version),
+            SaveStateError::Truncated => write!(f, "Save state is truncated."),
+            SaveStateError::InvalidValue(name) => write!(f, "Save state contains an invalid {}.", name),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+/// Writes values in the big-endian save state format.
+pub(crate) struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::with_header(MAGIC, FORMAT_VERSION)
+    }
+
+    /// Creates a writer for another file format sharing the encoding, identified by its own magic bytes and version.
+    pub fn with_header(magic: &[u8; 4], version: u16) -> Self {
+        let mut writer = Self { data: vec![] };
+        writer.bytes(magic);
+        writer.u16(version);
+        writer
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_be_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_be_bytes());
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    /// Writes a length-prefixed byte sequence.
+    pub fn vec(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.bytes(data);
+    }
+}
+
+/// Reads values in the big-endian save state format.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, SaveStateError> {
+        Self::with_header(data, MAGIC, FORMAT_VERSION)
+    }
+
+    /// Creates a reader for data written by `Writer::with_header`, checking the magic bytes and version.
+    pub fn with_header(data: &'a [u8], magic: &[u8; 4], version: u16) -> Result<Self, SaveStateError> {
+        let mut reader = Self { data };
+        if reader.bytes(magic.len()).map_err(|_| SaveStateError::InvalidHeader)? != magic {
+            return Err(SaveStateError::InvalidHeader);
+        }
+        let found_version = reader.u16()?;
+        if found_version != version {
+            return Err(SaveStateError::UnsupportedVersion(found_version));
+        }
+        Ok(reader)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidValue("boolean")),
+        }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        let mut array = [0x0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn bytes(&mut self, size: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() < size {
+            return Err(SaveStateError::Truncated);
+        }
+        let (bytes, data) = self.data.split_at(size);
+        self.data = data;
+        Ok(bytes)
+    }
+
+    /// Reads a length-prefixed byte sequence.
+    pub fn vec(&mut self) -> Result<Vec<u8>, SaveStateError> {
+        let size = self.u32()? as usize;
+        Ok(self.bytes(size)?.to_vec())
+    }
+
+    pub fn finish(self) -> Result<(), SaveStateError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(SaveStateError::InvalidValue("trailing data"))
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use chip8::Display;
+use crate::config::{Colors, Rgb};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// An RGB image of the display.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels in rows from the top, three bytes per pixel.
+    pub rgb: Vec<u8>,
+}
+
+impl Image {
+    /// Renders the display with every pixel scaled up to a square of the given size.
+    pub fn render(display: &Display, scale: u32, colors: &Colors) -> Self {
+        let width = display.width as u32 * scale;
+        let height = display.height as u32 * scale;
+        let mut rgb = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..display.height {
+            let mut row = Vec::with_capacity((width * 3) as usize);
+            for x in 0..display.width {
+                let Rgb(red, green, blue) = colors.pixel(display.pixel(x, y));
+                for _ in 0..scale {
+                    row.extend_from_slice(&[red, green, blue]);
+                }
+            }
+            for _ in 0..scale {
+                rgb.extend_from_slice(&row);
+            }
+        }
+        Self { width, height, rgb }
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), String> {
+        let write = || -> Result<(), Box<dyn Error>> {
+            let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width, self.height);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&self.rgb)?;
+            writer.finish()?;
+            Ok(())
+        };
+        write().map_err(|error| format!("Could not write screenshot {}: {}.", path.display(), error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut display = Display::new(64, 32, false);
+        display.apply_sprite(1, 0, &[0x80]);
+        let colors = Colors { background: Rgb(0, 0, 0), foreground: Rgb(0x33, 0xFF, 0x66), ..Colors::default() };
+        let image = Image::render(&display, 2, &colors);
+        assert_eq!((image.width, image.height), (128, 64));
+        assert_eq!(image.rgb.len(), 128 * 64 * 3);
+        let pixel = |x: usize, y: usize| &image.rgb[(y * 128 + x) * 3 .. (y * 128 + x) * 3 + 3];
+        assert_eq!(pixel(1, 1), &[0, 0, 0]);
+        assert_eq!(pixel(2, 0), &[0x33, 0xFF, 0x66]);
+        assert_eq!(pixel(3, 1), &[0x33, 0xFF, 0x66]);
+        assert_eq!(pixel(4, 0), &[0, 0, 0]);
+    }
+}
@@ -0,0 +1,406 @@
+use chip8::debugger::{self, Debugger, DebuggerCommand};
+use chip8::{DirtyRows, Display, Emulator, EmulatorInput, EmulatorOutput};
+use crate::config::{Colors, Config, Rgb};
+use crate::movie_session::MovieSession;
+use crate::phosphor::Phosphor;
+use crate::recording::Recorder;
+use crate::rom::Rom;
+use crate::screenshot::Image;
+use crate::speaker::Speaker;
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{BlendMode, Texture, WindowCanvas};
+use sdl2::rect::Rect;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub fn run(config: Config, mut emulator: Emulator, mut debugger: Option<Debugger>, mut recorder: Recorder, mut movie: Option<MovieSession>, rom: &Rom) -> Result<(), String> {
+    let key_bindings = key_bindings(&config)?;
+    let rewind_scancode = Scancode::from_name(&config.rewind_key).ok_or_else(|| format!("Unknown keyboard key: {}.", config.rewind_key))?;
+    let palettes = config.palettes();
+    let mut palette = 0;
+
+    let sdl_context = sdl2::init().expect("Could not initialize SDL2.");
+
+    let audio_subsystem = sdl_context.audio().expect("Could not initialize audio subsystem.");
+    let speaker_settings = config.speaker_settings;
+    let mut speaker = Speaker::new(audio_subsystem, speaker_settings);
+
+    let window_width = config.settings.display_width as u32 * config.window_scale;
+    let window_height = config.settings.display_height as u32 * config.window_scale;
+    let video_subsystem = sdl_context.video().expect("Could not initialize video subsystem.");
+    let window = video_subsystem
+        .window("Chip-8", window_width, window_height)
+        .position_centered()
+        .resizable()
+        .build()
+        .expect("Could not build window.");
+    let mut canvas = window.into_canvas().build().expect("Could not build canvas.");
+    canvas.set_blend_mode(BlendMode::Blend);
+    let texture_creator = canvas.texture_creator();
+    // The display is streamed into a texture with one texel per pixel, which is scaled up when copied to the canvas.
+    let mut texture: Option<Texture> = None;
+    let mut drawn_version = None;
+    let mut phosphor = Phosphor::new(config.persistence, config.decay_frames);
+    let mut event_pump = sdl_context.event_pump().expect("Could not obtain event pump.");
+
+    let mut frame_duration = emulator.frame_duration();
+    let mut input = EmulatorInput::new();
+    let mut output = emulator.output();
+    let mut shown_fault = None;
+    // The number of frames emulated since power-on, which keeps movies in sync when rewinding.
+    let mut frame: usize = 0;
+
+    let debugger_commands = debugger.as_ref().map(|_| {
+        println!("Debugger paused at {}\nType help for a list of commands.", debugger::location(emulator.state()));
+        print_prompt();
+        read_lines()
+    });
+
+    loop {
+        let frame_clock = Instant::now();
+
+        if let (Some(debugger), Some(commands)) = (&mut debugger, &debugger_commands) {
+            for line in commands.try_iter() {
+                let result = line.parse::<DebuggerCommand>().and_then(|command| debugger.execute(&mut emulator, command));
+                match result {
+                    Ok(text) if text.is_empty() => {},
+                    Ok(text) => println!("{}", text),
+                    Err(error) => println!("{}", error),
+                }
+                output = emulator.output();
+                print_prompt();
+            }
+        }
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit {..} |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    for path in recorder.finish()? {
+                        eprintln!("Saved recording to {}.", path.display());
+                    }
+                    if let Some(path) = movie.map(MovieSession::finish).transpose()?.flatten() {
+                        eprintln!("Saved movie to {}.", path.display());
+                    }
+                    return Ok(());
+                },
+                Event::KeyDown { keycode: Some(Keycode::M), .. } => {
+                    if speaker.volume() > 0 {
+                        speaker.set_volume(0);
+                    } else {
+                        speaker.set_volume(speaker_settings.volume);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => {
+                    palette = (palette + 1) % palettes.len();
+                    drawn_version = None;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } => {
+                    if let Err(error) = toggle_gif_recording(&mut recorder, rom) {
+                        eprintln!("{}", error);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+                    if let Err(error) = save_screenshot(&config, &palettes[palette], emulator.display(), rom) {
+                        eprintln!("{}", error);
+                    }
+                },
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } => {
+                    if let Some(slot) = save_slot(keycode) {
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            // Movies replay the input from power-on, which a loaded state would break.
+                            if movie.is_some() {
+                                eprintln!("Save states cannot be loaded while recording or playing a movie.");
+                                continue;
+                            }
+                            match load_state(&mut emulator, rom, slot) {
+                                Ok(()) => {
+                                    // The loaded settings may run at a different frame rate.
+                                    output = emulator.output();
+                                    frame_duration = emulator.frame_duration();
+                                },
+                                Err(error) => eprintln!("{}", error),
+                            }
+                        } else if let Err(error) = save_state(&emulator, rom, slot) {
+                            eprintln!("{}", error);
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        input.keypad.release_all_keys();
+        for scancode in event_pump.keyboard_state().pressed_scancodes() {
+            let key_pressed = key_bindings.get(&scancode);
+
+            if let Some(&key) = key_pressed {
+                input.keypad.set_key_pressed(key);
+            }
+        }
+
+        let rewinding = event_pump.keyboard_state().is_scancode_pressed(rewind_scancode);
+        let mut emulated = false;
+        if rewinding {
+            if let Some(rewound_output) = emulator.rewind() {
+                output = rewound_output;
+                frame = frame.saturating_sub(1);
+            }
+        } else if let Some(debugger) = &mut debugger {
+            emulated = !debugger.paused();
+            let (debugger_output, reason) = debugger.run_frame(&mut emulator, input.clone());
+            output = debugger_output;
+            if let Some(reason) = reason {
+                println!("\n{}\n{}", reason, debugger::location(emulator.state()));
+                print_prompt();
+            }
+        } else if !output.halted {
+            if let Some(movie) = &mut movie {
+                movie.next_frame(frame, &mut input.keypad);
+            }
+            output = emulator.run_frame(input.clone());
+            frame += 1;
+            emulated = true;
+        }
+        // Frames are only recorded as they are emulated forward, so that recordings are not stretched by pauses or
+        // played backwards while rewinding.
+        if emulated {
+            if let Err(error) = recorder.record(emulator.display(), emulator.settings().frame_rate) {
+                eprintln!("{}", error);
+            }
+        }
+        // Erased pixels keep fading out after the program halts, but not while it is paused, unless the debugger steps.
+        let advance_phosphor = emulated || output.halted || drawn_version != Some(output.display_version);
+        let phosphor_rows = if phosphor.enabled() && advance_phosphor {
+            phosphor.update(emulator.display(), &output)
+        } else {
+            DirtyRows::default()
+        };
+
+        if output.fault != shown_fault {
+            shown_fault = output.fault;
+            let title = match shown_fault {
+                Some(fault) => {
+                    if debugger.is_none() {
+                        eprintln!("Program halted by fault: {}\n{}", fault, debugger::stack(emulator.state()));
+                    }
+                    format!("Chip-8 - Halted: {}", fault)
+                },
+                None => "Chip-8".to_string(),
+            };
+            canvas.window_mut().set_title(&title).expect("Could not set window title.");
+        }
+
+        let paused = rewinding || debugger.as_ref().is_some_and(Debugger::paused);
+        speaker.set_audio_pattern(output.audio_pattern);
+        if output.sound_playing && !output.halted && !paused {
+            speaker.play()
+        } else {
+            speaker.pause()
+        }
+
+        let (window_width, window_height) = canvas.output_size().expect("Could not retrieve canvas output size.");
+        let display = emulator.display();
+        let (display_width, display_height) = (u32::from(display.width), u32::from(display.height));
+        let width_scale = window_width / display_width;
+        let heigh_scale = window_height / display_height;
+
+        if texture.as_ref().is_none_or(|texture| (texture.query().width, texture.query().height) != (display_width, display_height)) {
+            let new_texture = texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, display_width, display_height)
+                .expect("Could not create texture.");
+            texture = Some(new_texture);
+            drawn_version = None;
+        }
+        let texture = texture.as_mut().expect("Texture was not created.");
+        let colors = palettes[palette];
+        if phosphor.enabled() {
+            if !phosphor_rows.is_empty() || drawn_version.is_none() {
+                let rows = if drawn_version.is_none() { DirtyRows::all(display.height) } else { phosphor_rows };
+                // Erased pixels fade from the foreground color, as the planes they were lit in are not known.
+                let pixel_color = |x, y| match display.pixel(x, y) {
+                    0 => mix(colors.background, colors.foreground, phosphor.level(x, y)),
+                    pixel => colors.pixel(pixel),
+                };
+                update_texture(texture, display.width, rows, pixel_color);
+                drawn_version = Some(output.display_version);
+            }
+        } else if drawn_version != Some(output.display_version) {
+            let rows = dirty_rows(&output, drawn_version, display.height);
+            update_texture(texture, display.width, rows, |x, y| colors.pixel(display.pixel(x, y)));
+            drawn_version = Some(output.display_version);
+        }
+
+        canvas.set_draw_color(color(colors.background));
+        canvas.clear();
+        canvas.copy(texture, None, Rect::new(0, 0, display_width * width_scale, display_height * heigh_scale)).expect("Draw failed.");
+
+        if output.fault.is_some() {
+            draw_halt_screen(&mut canvas, window_width, window_height);
+        }
+
+        canvas.present();
+
+        let frame_elapsed_duration = frame_clock.elapsed();
+        if frame_elapsed_duration < frame_duration {
+            std::thread::sleep(frame_duration - frame_elapsed_duration);
+        }
+    }
+}
+
+/// The rows to redraw to bring the display drawn at the given version up to date.
+fn dirty_rows(output: &EmulatorOutput, drawn_version: Option<u64>, height: u8) -> DirtyRows {
+    // The rows changed in the last frame only suffice when the drawn display was at least as recent as its start.
+    match drawn_version {
+        Some(version) if version >= output.dirty_since_version => output.dirty_rows,
+        _ => DirtyRows::all(height),
+    }
+}
+
+/// Writes the given rows of the display into the texture, coloring each pixel with the given function.
+fn update_texture<F>(texture: &mut Texture, width: u8, rows: DirtyRows, pixel_color: F)
+where
+    F: Fn(u8, u8) -> Rgb,
+{
+    texture
+        .with_lock(None, |pixels, pitch| {
+            for y in rows.iter() {
+                let row = &mut pixels[y as usize * pitch ..];
+                for x in 0..width {
+                    let Rgb(r, g, b) = pixel_color(x, y);
+                    row[x as usize * 3 .. x as usize * 3 + 3].copy_from_slice(&[r, g, b]);
+                }
+            }
+        })
+        .expect("Could not update texture.");
+}
+
+/// Blends the background into the foreground by the given brightness, from 0 for the background to 255 for the foreground.
+fn mix(background: Rgb, foreground: Rgb, brightness: u8) -> Rgb {
+    let channel = |background: u8, foreground: u8| {
+        let (background, foreground, brightness) = (background as u32, foreground as u32, brightness as u32);
+        ((background * (255 - brightness) + foreground * brightness) / 255) as u8
+    };
+    Rgb(channel(background.0, foreground.0), channel(background.1, foreground.1), channel(background.2, foreground.2))
+}
+
+/// Dims the frozen display and frames it in red, to show that the program was halted by a fault.
+fn draw_halt_screen(canvas: &mut WindowCanvas, width: u32, height: u32) {
+    let border = (width.min(height) / 40).max(2);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    canvas.fill_rect(Rect::new(0, 0, width, height)).expect("Draw failed.");
+    canvas.set_draw_color(Color::RGB(0xD0, 0x20, 0x20));
+    let edges = [
+        Rect::new(0, 0, width, border),
+        Rect::new(0, (height - border) as i32, width, border),
+        Rect::new(0, 0, border, height),
+        Rect::new((width - border) as i32, 0, border, height),
+    ];
+    canvas.fill_rects(&edges).expect("Draw failed.");
+}
+
+/// Reads lines from standard input on a separate thread, so that the window stays responsive.
+fn read_lines() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn print_prompt() {
+    print!("(chip8) ");
+    io::stdout().flush().expect("Could not flush standard output.");
+}
+
+/// The save state slot bound to a function key (F1-F4).
+fn save_slot(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        _ => None,
+    }
+}
+
+/// Save states are stored per ROM in $XDG_DATA_HOME/chip8/states, named by the SHA-1 hash of the ROM.
+fn save_state_path(rom: &Rom, slot: u8) -> Result<PathBuf, String> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))
+        .ok_or("Could not determine the save state directory.")?;
+    Ok(data_home.join("chip8").join("states").join(format!("{}.{}.state", rom.sha1, slot)))
+}
+
+fn save_state(emulator: &Emulator, rom: &Rom, slot: u8) -> Result<(), String> {
+    let path = save_state_path(rom, slot)?;
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).map_err(|error| format!("Could not create directory {}: {}.", directory.display(), error))?;
+    }
+    fs::write(&path, emulator.state().save()).map_err(|error| format!("Could not write save state {}: {}.", path.display(), error))?;
+    eprintln!("Saved state to slot {}.", slot);
+    Ok(())
+}
+
+fn load_state(emulator: &mut Emulator, rom: &Rom, slot: u8) -> Result<(), String> {
+    let path = save_state_path(rom, slot)?;
+    let data = fs::read(&path).map_err(|_| format!("Save state slot {} is empty.", slot))?;
+    emulator.load_state(&data).map_err(|error| format!("Could not load save state {}: {}", path.display(), error))?;
+    eprintln!("Loaded state from slot {}.", slot);
+    Ok(())
+}
+
+/// A path in the current directory for a file with the given extension, named after the ROM and the current time.
+fn output_path(rom: &Rom, extension: &str) -> PathBuf {
+    let name = Path::new(&rom.name).file_stem().map_or_else(|| rom.name.clone(), |stem| stem.to_string_lossy().into_owned());
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_millis());
+    PathBuf::from(format!("{}-{}.{}", name, timestamp, extension))
+}
+
+/// Saves a screenshot in the current directory, in the given colors.
+fn save_screenshot(config: &Config, colors: &Colors, display: &Display, rom: &Rom) -> Result<(), String> {
+    let path = output_path(rom, "png");
+    Image::render(display, config.screenshot_scale, colors).save_png(&path)?;
+    eprintln!("Saved screenshot to {}.", path.display());
+    Ok(())
+}
+
+/// Finishes the GIF recording, or starts one in the current directory if there is none.
+fn toggle_gif_recording(recorder: &mut Recorder, rom: &Rom) -> Result<(), String> {
+    match recorder.stop_gif()? {
+        Some(path) => eprintln!("Saved recording to {}.", path.display()),
+        None => {
+            let path = output_path(rom, "gif");
+            recorder.start_gif(&path)?;
+            eprintln!("Recording to {}.", path.display());
+        },
+    }
+    Ok(())
+}
+
+fn key_bindings(config: &Config) -> Result<HashMap<Scancode, u8>, String> {
+    let mut key_bindings = HashMap::new();
+    for (key, name) in config.keys.iter().enumerate() {
+        let scancode = Scancode::from_name(name).ok_or_else(|| format!("Unknown keyboard key: {}.", name))?;
+        key_bindings.insert(scancode, key as u8);
+    }
+    Ok(key_bindings)
+}
+
+fn color(rgb: Rgb) -> Color {
+    Color::RGB(rgb.0, rgb.1, rgb.2)
+}
@@ -1,18 +1,71 @@
-#[derive(Clone, Copy)]
+use crate::savestate::{Reader, SaveStateError, Writer};
+use std::fmt;
+use std::str::FromStr;
+
+/// A CHIP-8 platform, determining the set of quirks used by programs written for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    /// The original CHIP-8 interpreter for the COSMAC VIP.
+    CosmacVip,
+    /// CHIP-48 for the HP-48 calculators.
+    Chip48,
+    /// SUPER-CHIP 1.1 for the HP-48 calculators.
+    SuperChip11,
+    /// XO-CHIP, as implemented by Octo.
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 4] = [Platform::CosmacVip, Platform::Chip48, Platform::SuperChip11, Platform::XoChip];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::CosmacVip => "cosmac-vip",
+            Platform::Chip48 => "chip-48",
+            Platform::SuperChip11 => "super-chip",
+            Platform::XoChip => "xo-chip",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Platform::ALL
+            .into_iter()
+            .find(|platform| platform.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown platform: {}.", name))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Settings {
-    /// Clock speed in Hz.
-    pub frame_rate: u16,
     /// Frame rate in Hz.
+    pub frame_rate: u16,
+    /// Clock speed in Hz.
     pub clock_speed: u16,
     /// The memory address at which sprite data is stored.
     pub sprite_start_address: u16,
+    /// The memory address at which large (SUPER-CHIP) sprite data is stored.
+    pub large_sprite_start_address: u16,
     /// The memory address at which programs start.
     pub program_start_address: u16,
-    /// The memory size in bytes.
-    pub memory_size: u16,
-    /// The width of the virtual display in px.
+    /// The memory size in bytes (up to 64 KiB).
+    pub memory_size: u32,
+    /// The maximum number of nested subroutine calls.
+    pub stack_depth: u16,
+    /// The seed of the random number generator, or `None` to pick one at random.
+    pub random_seed: Option<u32>,
+    /// The width of the virtual display in px in low resolution mode. High resolution mode doubles it.
     pub display_width: u8,
-    /// The height of the virtual display in px.
+    /// The height of the virtual display in px in low resolution mode. High resolution mode doubles it.
     pub display_height: u8,
     /// Shift right (8XY6) and left (8XYE) in-place on register VX rather than from VY.
     pub use_in_place_shift: bool,
@@ -26,6 +79,136 @@ pub struct Settings {
     pub use_sprite_wrapping: bool,
     /// Sprites are only applied at the beginning of next frame.
     pub use_sprite_draw_delay: bool,
+    /// DXY0 draws a 16x16 sprite (SUPER-CHIP), rather than a sprite without rows.
+    pub use_large_sprites: bool,
+    /// In low resolution mode, DXY0 draws an 8x16 sprite and scrolling moves half as far, as SUPER-CHIP 1.1 does.
+    pub use_legacy_low_resolution: bool,
+}
+
+impl Settings {
+    /// Settings with the clock speed, memory size, display size and quirks of the given platform.
+    pub fn preset(platform: Platform) -> Self {
+        let defaults = Self::default();
+        match platform {
+            Platform::CosmacVip => Self {
+                clock_speed: 600,
+                memory_size: 0x1000,
+                stack_depth: 12,
+                display_width: 64,
+                display_height: 32,
+                use_in_place_shift: false,
+                use_flexible_jump_offset: false,
+                use_auto_address_increments: true,
+                use_flag_reset_on_logic_ops: true,
+                use_sprite_wrapping: false,
+                use_sprite_draw_delay: true,
+                use_large_sprites: false,
+                use_legacy_low_resolution: false,
+                ..defaults
+            },
+            Platform::Chip48 => Self {
+                clock_speed: 1000,
+                memory_size: 0x1000,
+                stack_depth: 16,
+                display_width: 64,
+                display_height: 32,
+                use_in_place_shift: true,
+                use_flexible_jump_offset: true,
+                use_auto_address_increments: false,
+                use_flag_reset_on_logic_ops: false,
+                use_sprite_wrapping: false,
+                use_sprite_draw_delay: false,
+                use_large_sprites: false,
+                use_legacy_low_resolution: false,
+                ..defaults
+            },
+            Platform::SuperChip11 => Self {
+                clock_speed: 1800,
+                memory_size: 0x1000,
+                stack_depth: 16,
+                display_width: 64,
+                display_height: 32,
+                use_in_place_shift: true,
+                use_flexible_jump_offset: true,
+                use_auto_address_increments: false,
+                use_flag_reset_on_logic_ops: false,
+                use_sprite_wrapping: false,
+                use_sprite_draw_delay: false,
+                use_large_sprites: true,
+                use_legacy_low_resolution: true,
+                ..defaults
+            },
+            Platform::XoChip => Self {
+                clock_speed: 12000,
+                memory_size: 0x10000,
+                stack_depth: 16,
+                display_width: 64,
+                display_height: 32,
+                use_in_place_shift: false,
+                use_flexible_jump_offset: false,
+                use_auto_address_increments: true,
+                use_flag_reset_on_logic_ops: false,
+                use_sprite_wrapping: true,
+                use_sprite_draw_delay: false,
+                use_large_sprites: true,
+                use_legacy_low_resolution: false,
+                ..defaults
+            },
+        }
+    }
+
+    pub(crate) fn save_to(&self, writer: &mut Writer) {
+        writer.u16(self.frame_rate);
+        writer.u16(self.clock_speed);
+        writer.u16(self.sprite_start_address);
+        writer.u16(self.large_sprite_start_address);
+        writer.u16(self.program_start_address);
+        writer.u32(self.memory_size);
+        writer.u16(self.stack_depth);
+        writer.bool(self.random_seed.is_some());
+        writer.u32(self.random_seed.unwrap_or(0));
+        writer.u8(self.display_width);
+        writer.u8(self.display_height);
+        writer.bool(self.use_in_place_shift);
+        writer.bool(self.use_flexible_jump_offset);
+        writer.bool(self.use_auto_address_increments);
+        writer.bool(self.use_flag_reset_on_logic_ops);
+        writer.bool(self.use_sprite_wrapping);
+        writer.bool(self.use_sprite_draw_delay);
+        writer.bool(self.use_large_sprites);
+        writer.bool(self.use_legacy_low_resolution);
+    }
+
+    pub(crate) fn load_from(reader: &mut Reader) -> Result<Self, SaveStateError> {
+        let settings = Self {
+            frame_rate: reader.u16()?,
+            clock_speed: reader.u16()?,
+            sprite_start_address: reader.u16()?,
+            large_sprite_start_address: reader.u16()?,
+            program_start_address: reader.u16()?,
+            memory_size: reader.u32()?,
+            stack_depth: reader.u16()?,
+            random_seed: {
+                let has_random_seed = reader.bool()?;
+                let random_seed = reader.u32()?;
+                has_random_seed.then_some(random_seed)
+            },
+            display_width: reader.u8()?,
+            display_height: reader.u8()?,
+            use_in_place_shift: reader.bool()?,
+            use_flexible_jump_offset: reader.bool()?,
+            use_auto_address_increments: reader.bool()?,
+            use_flag_reset_on_logic_ops: reader.bool()?,
+            use_sprite_wrapping: reader.bool()?,
+            use_sprite_draw_delay: reader.bool()?,
+            use_large_sprites: reader.bool()?,
+            use_legacy_low_resolution: reader.bool()?,
+        };
+        if settings.frame_rate == 0 || settings.clock_speed == 0 || settings.memory_size > 0x10000 || settings.stack_depth == 0 || settings.display_width == 0 || settings.display_height == 0 {
+            return Err(SaveStateError::InvalidValue("settings"));
+        }
+        Ok(settings)
+    }
 }
 
 impl Default for Settings {
@@ -34,8 +217,11 @@ impl Default for Settings {
             frame_rate: 60,
             clock_speed: 500,
             sprite_start_address: 0x0,
+            large_sprite_start_address: 0x50,
             program_start_address: 0x200,
             memory_size: 0x1000,
+            stack_depth: 16,
+            random_seed: None,
             display_width: 64,
             display_height: 32,
             use_in_place_shift: false,
@@ -44,6 +230,123 @@ impl Default for Settings {
             use_flag_reset_on_logic_ops: false,
             use_sprite_wrapping: false,
             use_sprite_draw_delay: false,
+            use_large_sprites: true,
+            use_legacy_low_resolution: false,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_platform_names() {
+        for platform in Platform::ALL {
+            assert_eq!(platform.name().parse::<Platform>(), Ok(platform));
+        }
+        assert!("chip-9".parse::<Platform>().is_err());
+    }
+
+    #[test]
+    fn test_stack_depth_presets() {
+        assert_eq!(Settings::preset(Platform::CosmacVip).stack_depth, 12);
+        assert_eq!(Settings::preset(Platform::SuperChip11).stack_depth, 16);
+    }
+
+    #[test]
+    fn test_cosmac_vip_preset() {
+        let expected = Settings {
+            clock_speed: 600,
+            memory_size: 0x1000,
+            stack_depth: 12,
+            display_width: 64,
+            display_height: 32,
+            use_in_place_shift: false,
+            use_flexible_jump_offset: false,
+            use_auto_address_increments: true,
+            use_flag_reset_on_logic_ops: true,
+            use_sprite_wrapping: false,
+            use_sprite_draw_delay: true,
+            use_large_sprites: false,
+            use_legacy_low_resolution: false,
+            ..Settings::default()
+        };
+        assert_eq!(Settings::preset(Platform::CosmacVip), expected);
+    }
+
+    #[test]
+    fn test_chip_48_preset() {
+        let expected = Settings {
+            clock_speed: 1000,
+            memory_size: 0x1000,
+            stack_depth: 16,
+            display_width: 64,
+            display_height: 32,
+            use_in_place_shift: true,
+            use_flexible_jump_offset: true,
+            use_auto_address_increments: false,
+            use_flag_reset_on_logic_ops: false,
+            use_sprite_wrapping: false,
+            use_sprite_draw_delay: false,
+            use_large_sprites: false,
+            use_legacy_low_resolution: false,
+            ..Settings::default()
+        };
+        assert_eq!(Settings::preset(Platform::Chip48), expected);
+    }
+
+    #[test]
+    fn test_super_chip_preset() {
+        let expected = Settings {
+            clock_speed: 1800,
+            memory_size: 0x1000,
+            stack_depth: 16,
+            display_width: 64,
+            display_height: 32,
+            use_in_place_shift: true,
+            use_flexible_jump_offset: true,
+            use_auto_address_increments: false,
+            use_flag_reset_on_logic_ops: false,
+            use_sprite_wrapping: false,
+            use_sprite_draw_delay: false,
+            use_large_sprites: true,
+            use_legacy_low_resolution: true,
+            ..Settings::default()
+        };
+        assert_eq!(Settings::preset(Platform::SuperChip11), expected);
+    }
+
+    #[test]
+    fn test_xo_chip_preset() {
+        let expected = Settings {
+            clock_speed: 12000,
+            memory_size: 0x10000,
+            stack_depth: 16,
+            display_width: 64,
+            display_height: 32,
+            use_in_place_shift: false,
+            use_flexible_jump_offset: false,
+            use_auto_address_increments: true,
+            use_flag_reset_on_logic_ops: false,
+            use_sprite_wrapping: true,
+            use_sprite_draw_delay: false,
+            use_large_sprites: true,
+            use_legacy_low_resolution: false,
+            ..Settings::default()
+        };
+        assert_eq!(Settings::preset(Platform::XoChip), expected);
+    }
+
+    #[test]
+    fn test_presets_keep_default_addresses() {
+        for platform in Platform::ALL {
+            let settings = Settings::preset(platform);
+            assert_eq!(settings.frame_rate, 60);
+            assert_eq!(settings.program_start_address, 0x200);
+            assert_eq!(settings.sprite_start_address, 0x0);
+            assert_eq!(settings.large_sprite_start_address, 0x50);
+            assert_eq!(settings.random_seed, None);
+        }
+    }
+}
@@ -1,25 +1,12 @@
 extern crate sdl2;
 
+use chip8::audio::{AudioPattern, SpeakerSettings};
 use sdl2::AudioSubsystem;
 use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
 
-#[derive(Clone, Copy)]
-pub struct SpeakerSettings {
-    pub frequency: u16,
-    pub volume: u8,
-}
-
-impl Default for SpeakerSettings {
-    fn default() -> Self {
-        Self {
-            frequency: 220,
-            volume: 75,
-        }
-    }
-}
-
 struct Beep {
     settings: SpeakerSettings,
+    pattern: Option<AudioPattern>,
     sample_frequency: f32,
     phase: f32,
 }
@@ -28,11 +15,24 @@ impl AudioCallback for Beep {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        let amplitude = self.settings.volume as f32 / u8::MAX as  f32;
-        let phase_delta = self.settings.frequency as f32 / self.sample_frequency as f32;
-        for i in 0..out.len() {
-            out[i] = if self.phase <= 0.5 { amplitude } else { -amplitude };
-            self.phase = (self.phase + phase_delta) % 1.0;
+        let amplitude = self.settings.volume as f32 / u8::MAX as f32;
+        match self.pattern {
+            Some(pattern) => {
+                let sample_count = pattern.sample_count() as f32;
+                let phase_delta = pattern.playback_rate() / sample_count / self.sample_frequency;
+                for sample in out.iter_mut() {
+                    let index = (self.phase * sample_count) as usize;
+                    *sample = if pattern.sample(index) { amplitude } else { -amplitude };
+                    self.phase = (self.phase + phase_delta) % 1.0;
+                }
+            },
+            None => {
+                let phase_delta = self.settings.frequency as f32 / self.sample_frequency;
+                for sample in out.iter_mut() {
+                    *sample = if self.phase <= 0.5 { amplitude } else { -amplitude };
+                    self.phase = (self.phase + phase_delta) % 1.0;
+                }
+            }
         }
     }
 }
@@ -51,14 +51,15 @@ impl Speaker {
 
         let audio_device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
             Beep {
-                settings: settings,
+                settings,
+                pattern: None,
                 sample_frequency: spec.freq as f32,
                 phase: 0.0,
             }
         }).expect("Could not create audio device.");
 
         Self {
-            audio_device: audio_device,
+            audio_device,
         }
     }
 
@@ -72,6 +73,12 @@ impl Speaker {
         guard.settings.volume = volume;
     }
 
+    /// Sets the XO-CHIP audio pattern to play instead of the default square wave beep.
+    pub fn set_audio_pattern(&mut self, pattern: Option<AudioPattern>) {
+        let mut guard = self.audio_device.lock();
+        guard.pattern = pattern;
+    }
+
     pub fn play(&mut self) {
         self.audio_device.resume();
     }
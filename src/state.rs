@@ -1,5 +1,10 @@
+use std::fmt;
+use crate::audio::{AudioPattern, AUDIO_PATTERN_SIZE, DEFAULT_PITCH};
 use crate::display::Display;
+use crate::instructions::Instruction;
 use crate::keypad::Keypad;
+use crate::random::Random;
+use crate::savestate::{Reader, SaveStateError, Writer};
 use crate::settings::Settings;
 
 const REGISTER_COUNT: usize = 16;
@@ -21,49 +26,229 @@ const SPRITE_DATA: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
+const FLAG_COUNT: usize = 16;
+const LARGE_SPRITE_DATA: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// A memory or stack access that the state cannot satisfy, or settings it cannot be created with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// A read or write of the given number of bytes extends beyond the end of memory.
+    MemoryOutOfBounds { address: u16, length: usize },
+    /// A return address, or the address of a font sprite, lies beyond the end of memory.
+    AddressOutOfBounds { address: u16 },
+    /// A return was attempted with an empty stack.
+    StackUnderflow,
+    /// A call was attempted with the stack at its maximum depth.
+    StackOverflow,
+    /// The named setting is not supported, such as a memory size over 64 KiB.
+    InvalidSettings(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::MemoryOutOfBounds { address, length } => write!(f, "Memory access out of bounds: {} bytes at 0x{:04X}.", length, address),
+            StateError::AddressOutOfBounds { address } => write!(f, "Address out of bounds: 0x{:04X}.", address),
+            StateError::StackUnderflow => write!(f, "Stack underflow."),
+            StateError::StackOverflow => write!(f, "Stack overflow."),
+            StateError::InvalidSettings(name) => write!(f, "Settings contain an invalid {}.", name),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
 
+#[derive(Clone)]
 pub struct State {
+    settings: Settings,
     memory: Vec<u8>,
     registers: [u8; REGISTER_COUNT],
+    flags: [u8; FLAG_COUNT],
     stack: Vec<u16>,
     address_register: u16,
+    random: Random,
+    exited: bool,
     pub program_counter: u16,
     pub delay_register: u8,
     pub sound_register: u8,
+    pub audio_buffer: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pub pitch: u8,
     pub display: Display,
     pub keypad: Keypad,
 }
 
 impl State {
-    pub fn new(settings: Settings, program: Vec<u8>) -> Self {
+    /// Creates the power-on state with the program and font sprites loaded, or fails if the settings are not supported
+    /// or the program does not fit in memory.
+    pub fn new(settings: Settings, program: Vec<u8>) -> Result<Self, StateError> {
+        if settings.memory_size > 0x10000 {
+            return Err(StateError::InvalidSettings("memory size"));
+        }
+        // High resolution mode doubles the display size, which must still fit in a byte.
+        let display_sizes = [settings.display_width, settings.display_height];
+        if display_sizes.iter().any(|&size| size == 0 || size >= 0x80) {
+            return Err(StateError::InvalidSettings("display size"));
+        }
         let mut state = Self {
+            settings,
             memory: vec![0x0; settings.memory_size as usize],
             registers: [0x0; REGISTER_COUNT],
+            flags: [0x0; FLAG_COUNT],
             stack: vec![],
             address_register: 0,
+            random: Random::new(settings.random_seed.unwrap_or_else(rand::random)),
+            exited: false,
             program_counter: settings.program_start_address,
             delay_register: 0,
             sound_register: 0,
+            audio_buffer: None,
+            pitch: DEFAULT_PITCH,
             display: Display::new(settings.display_width, settings.display_height, settings.use_sprite_wrapping),
             keypad: Keypad::new(),
         };
 
-        state.write_memory(settings.program_start_address, &program);
-        state.write_memory(settings.sprite_start_address, &SPRITE_DATA);
+        state.write_memory(settings.program_start_address, &program)?;
+        state.write_memory(settings.sprite_start_address, &SPRITE_DATA)?;
+        state.write_memory(settings.large_sprite_start_address, &LARGE_SPRITE_DATA)?;
 
-        state
+        Ok(state)
     }
 
-    pub fn read_memory(&self, address: u16, size: u16) -> &[u8] {
+    /// The settings the state was created with.
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    /// Serializes the full machine state, along with the settings in effect, into a versioned binary format.
+    pub fn save(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.settings.save_to(&mut writer);
+        writer.vec(&self.memory);
+        writer.bytes(&self.registers);
+        writer.bytes(&self.flags);
+        writer.u16(self.stack.len() as u16);
+        for &address in &self.stack {
+            writer.u16(address);
+        }
+        writer.u16(self.address_register);
+        self.random.save_to(&mut writer);
+        writer.bool(self.exited);
+        writer.u16(self.program_counter);
+        writer.u8(self.delay_register);
+        writer.u8(self.sound_register);
+        writer.bool(self.audio_buffer.is_some());
+        writer.bytes(&self.audio_buffer.unwrap_or_default());
+        writer.u8(self.pitch);
+        self.display.save_to(&mut writer);
+        self.keypad.save_to(&mut writer);
+        writer.finish()
+    }
+
+    /// Restores a machine state serialized by `save`.
+    pub fn load(data: &[u8]) -> Result<Self, SaveStateError> {
+        let mut reader = Reader::new(data)?;
+        let settings = Settings::load_from(&mut reader)?;
+        let memory = reader.vec()?;
+        if memory.len() != settings.memory_size as usize {
+            return Err(SaveStateError::InvalidValue("memory size"));
+        }
+        let registers = reader.array()?;
+        let flags = reader.array()?;
+        let stack_size = reader.u16()?;
+        if stack_size > settings.stack_depth {
+            return Err(SaveStateError::InvalidValue("stack size"));
+        }
+        let stack = (0..stack_size).map(|_| reader.u16()).collect::<Result<Vec<u16>, _>>()?;
+        if stack.iter().any(|&address| address as usize >= memory.len()) {
+            return Err(SaveStateError::InvalidValue("return address"));
+        }
+        let address_register = reader.u16()?;
+        let random = Random::load_from(&mut reader)?;
+        let exited = reader.bool()?;
+        let program_counter = reader.u16()?;
+        // A program counter just past the end of memory is where a program that ran off the end stopped.
+        if program_counter as usize > memory.len() {
+            return Err(SaveStateError::InvalidValue("program counter"));
+        }
+        let delay_register = reader.u8()?;
+        let sound_register = reader.u8()?;
+        let has_audio_buffer = reader.bool()?;
+        let audio_buffer = reader.array()?;
+        let state = Self {
+            settings,
+            memory,
+            registers,
+            flags,
+            stack,
+            address_register,
+            random,
+            exited,
+            program_counter,
+            delay_register,
+            sound_register,
+            audio_buffer: has_audio_buffer.then_some(audio_buffer),
+            pitch: reader.u8()?,
+            display: Display::load_from(&mut reader)?,
+            keypad: Keypad::load_from(&mut reader)?,
+        };
+        reader.finish()?;
+        // The display checks that its size is consistent with its low resolution size, which is less than 128x128.
+        let scale = if state.display.high_resolution() { 2 } else { 1 };
+        if (state.display.width as u16, state.display.height as u16) != (settings.display_width as u16 * scale, settings.display_height as u16 * scale) {
+            return Err(SaveStateError::InvalidValue("display size"));
+        }
+        Ok(state)
+    }
+
+    /// The entire memory.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn read_memory(&self, address: u16, size: u16) -> Result<&[u8], StateError> {
         let address_range_end = address as usize + size as usize;
-        assert!(address_range_end <= self.memory.len(), "Address range out of bounds. Start address: {}, end address: {}.", address, address_range_end - 1);
-        &self.memory[address as usize .. address_range_end]
+        if address_range_end > self.memory.len() {
+            return Err(StateError::MemoryOutOfBounds { address, length: size as usize });
+        }
+        Ok(&self.memory[address as usize .. address_range_end])
     }
 
-    pub fn write_memory(&mut self, address: u16, data: &[u8]) {
+    pub fn write_memory(&mut self, address: u16, data: &[u8]) -> Result<(), StateError> {
         let address_range_end = address as usize + data.len();
-        assert!(address_range_end <= self.memory.len(), "Address range out of bounds. Start address: {}, end address: {}.", address, address_range_end - 1);
+        if address_range_end > self.memory.len() {
+            return Err(StateError::MemoryOutOfBounds { address, length: data.len() });
+        }
         self.memory[address as usize .. address_range_end].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Reads a 16-bit big-endian word, yielding 0 for addresses outside memory.
+    pub fn read_word(&self, address: u16) -> u16 {
+        let high = self.memory.get(address as usize).copied().unwrap_or(0);
+        let low = self.memory.get(address as usize + 1).copied().unwrap_or(0);
+        (high as u16) << 8 | low as u16
+    }
+
+    /// Decodes the instruction at the given address, without affecting the program counter.
+    pub fn instruction(&self, address: u16) -> Instruction {
+        Instruction::decode_long(self.read_word(address), self.read_word(address.wrapping_add(2)))
     }
 
     pub fn read_registers(&self, end_register: u8) -> &[u8] {
@@ -75,6 +260,15 @@ impl State {
         self.registers[0 .. data.len()].copy_from_slice(data);
     }
 
+    pub fn read_flags(&self, end_register: u8) -> &[u8] {
+        &self.flags[0 .. end_register as usize + 1]
+    }
+
+    pub fn write_flags(&mut self, data: &[u8]) {
+        assert!(data.len() <= FLAG_COUNT, "Data exceeds flag count. Data length: {}.", data.len());
+        self.flags[0 .. data.len()].copy_from_slice(data);
+    }
+
     pub fn register(&self, register: u8) -> u8 {
         assert!(register < REGISTER_COUNT as u8, "Invalid register: {}.", register);
         self.registers[register as usize]
@@ -85,34 +279,73 @@ impl State {
         self.registers[register as usize] = value;
     }
 
+    /// Whether the program exited (00FD) or ran past the end of memory.
     pub fn program_terminated(&self) -> bool {
-        (self.program_counter as usize) >= self.memory.len()
+        self.exited || (self.program_counter as usize) >= self.memory.len()
+    }
+
+    pub fn exit(&mut self) {
+        self.exited = true;
     }
 
+    /// Advances the program counter by a word. Running past the end of a 64 KiB memory, where the program counter would
+    /// wrap around, terminates the program just like running past the end of a smaller memory does.
     pub fn increment_program_counter(&mut self) {
-        self.program_counter += 2;
+        match self.program_counter.checked_add(2) {
+            Some(program_counter) => self.program_counter = program_counter,
+            None => self.exited = true,
+        }
     }
 
     pub fn decrement_program_counter(&mut self) {
-        self.program_counter -= 2;
+        self.program_counter = self.program_counter.wrapping_sub(2);
+    }
+
+    /// Skips the instruction at the program counter, taking four byte long instructions into account.
+    pub fn skip_instruction(&mut self) {
+        let instruction = self.read_word(self.program_counter);
+        if Instruction::is_long(instruction) {
+            self.increment_program_counter();
+        }
+        self.increment_program_counter();
     }
 
     pub fn address_register(&self) -> u16 {
         self.address_register
     }
 
+    /// Sets the address register, which may point anywhere, as memory is only checked when it is accessed.
     pub fn set_address_register(&mut self, address: u16) {
-        assert!((address as usize) < self.memory.len(), "Address out of bounds. Address: {}.", address);
         self.address_register = address;
     }
 
-    pub fn pop_return_address(&mut self) -> u16 {
-        self.stack.pop().expect("Cannot pop return address as stack is empty.")
+    /// The random number generator, including the seed it was created with.
+    pub fn random(&self) -> &Random {
+        &self.random
+    }
+
+    pub fn random_byte(&mut self) -> u8 {
+        self.random.next_byte()
+    }
+
+    /// The return addresses on the stack, outermost first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn pop_return_address(&mut self) -> Result<u16, StateError> {
+        self.stack.pop().ok_or(StateError::StackUnderflow)
     }
 
-    pub fn push_return_address(&mut self, address: u16) {
-        assert!((address as usize) < self.memory.len(), "Address out of bounds. Address: {}.", address);
+    pub fn push_return_address(&mut self, address: u16) -> Result<(), StateError> {
+        if (address as usize) >= self.memory.len() {
+            return Err(StateError::AddressOutOfBounds { address });
+        }
+        if self.stack.len() >= self.settings.stack_depth as usize {
+            return Err(StateError::StackOverflow);
+        }
         self.stack.push(address);
+        Ok(())
     }
 
     pub fn decrement_delay_register(&mut self) {
@@ -130,4 +363,110 @@ impl State {
     pub fn sound_playing(&self) -> bool {
         self.sound_register > 0
     }
-}
\ No newline at end of file
+
+    pub fn audio_pattern(&self) -> Option<AudioPattern> {
+        self.audio_buffer.map(|buffer| AudioPattern::new(buffer, self.pitch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let settings = Settings { use_sprite_wrapping: true, ..Settings::default() };
+        let mut state = State::new(settings, vec![0x12, 0x34]).unwrap();
+        state.set_register(0x3, 0x42);
+        state.set_address_register(0x300);
+        state.push_return_address(0x204).unwrap();
+        state.program_counter = 0x220;
+        state.delay_register = 10;
+        state.audio_buffer = Some([0xAA; AUDIO_PATTERN_SIZE]);
+        state.display.apply_sprite(1, 2, &[0xFF]);
+        state.keypad.set_key_pressed(0xA);
+
+        let mut loaded = State::load(&state.save()).unwrap();
+        assert_eq!(loaded.save(), state.save());
+        assert_eq!(loaded.settings(), settings);
+        assert_eq!(loaded.read_memory(0x200, 2), Ok(&[0x12, 0x34][..]));
+        assert_eq!(loaded.register(0x3), 0x42);
+        assert_eq!(loaded.address_register(), 0x300);
+        assert_eq!(loaded.pop_return_address(), Ok(0x204));
+        assert_eq!(loaded.program_counter, 0x220);
+        assert_eq!(loaded.delay_register, 10);
+        assert_eq!(loaded.audio_buffer, state.audio_buffer);
+        assert_eq!(loaded.display.visible_pixels(), state.display.visible_pixels());
+        assert!(loaded.keypad.key_pressed(0xA));
+    }
+
+    #[test]
+    fn test_out_of_bounds_access() {
+        let mut state = State::new(Settings::default(), vec![]).unwrap();
+        assert_eq!(state.read_memory(0xFFE, 4), Err(StateError::MemoryOutOfBounds { address: 0xFFE, length: 4 }));
+        assert_eq!(state.write_memory(0xFFF, &[0x1, 0x2]), Err(StateError::MemoryOutOfBounds { address: 0xFFF, length: 2 }));
+        assert_eq!(state.push_return_address(0x1000), Err(StateError::AddressOutOfBounds { address: 0x1000 }));
+        assert_eq!(state.pop_return_address(), Err(StateError::StackUnderflow));
+    }
+
+    #[test]
+    fn test_new_with_invalid_settings() {
+        let settings = Settings::default();
+        assert_eq!(State::new(settings, vec![0x0; 0xE01]).err(), Some(StateError::MemoryOutOfBounds { address: 0x200, length: 0xE01 }));
+        assert!(State::new(settings, vec![0x0; 0xE00]).is_ok());
+        assert_eq!(State::new(Settings { memory_size: 0x10001, ..settings }, vec![]).err(), Some(StateError::InvalidSettings("memory size")));
+        let font_past_end = Settings { large_sprite_start_address: 0xFF0, ..settings };
+        assert!(matches!(State::new(font_past_end, vec![]), Err(StateError::MemoryOutOfBounds { address: 0xFF0, .. })));
+    }
+
+    #[test]
+    fn test_new_with_invalid_display_size() {
+        let settings = Settings { display_width: 127, display_height: 127, ..Settings::default() };
+        let mut state = State::new(settings, vec![]).unwrap();
+        state.display.set_high_resolution(true);
+        assert_eq!((state.display.width, state.display.height), (254, 254));
+        for (display_width, display_height) in [(128, 32), (64, 128), (0, 32)] {
+            let settings = Settings { display_width, display_height, ..Settings::default() };
+            assert_eq!(State::new(settings, vec![]).err(), Some(StateError::InvalidSettings("display size")));
+        }
+    }
+
+    #[test]
+    fn test_run_past_end_of_memory() {
+        for memory_size in [0x1000, 0x10000] {
+            let mut state = State::new(Settings { memory_size, ..Settings::default() }, vec![]).unwrap();
+            state.program_counter = (memory_size - 4) as u16;
+            state.increment_program_counter();
+            assert!(!state.program_terminated());
+            state.increment_program_counter();
+            assert!(state.program_terminated());
+        }
+    }
+
+    #[test]
+    fn test_load_invalid_data() {
+        let data = State::new(Settings::default(), vec![]).unwrap().save();
+        assert_eq!(State::load(b"ROM").err(), Some(SaveStateError::InvalidHeader));
+        assert_eq!(State::load(&data[.. data.len() - 1]).err(), Some(SaveStateError::Truncated));
+
+        let mut future_version = data.clone();
+        future_version[5] += 1;
+        assert!(matches!(State::load(&future_version), Err(SaveStateError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn test_load_inconsistent_state() {
+        let saved = |change: fn(&mut State)| {
+            let mut state = State::new(Settings::default(), vec![]).unwrap();
+            change(&mut state);
+            State::load(&state.save()).err()
+        };
+        assert_eq!(saved(|state| state.program_counter = 0x1000), None);
+        assert_eq!(saved(|state| state.program_counter = 0x1002), Some(SaveStateError::InvalidValue("program counter")));
+        assert_eq!(saved(|state| state.stack.push(0x1000)), Some(SaveStateError::InvalidValue("return address")));
+        assert_eq!(saved(|state| state.stack = vec![0x200; state.settings.stack_depth as usize + 1]), Some(SaveStateError::InvalidValue("stack size")));
+        assert_eq!(saved(|state| state.display = Display::new(128, 64, false)), Some(SaveStateError::InvalidValue("display")));
+        assert_eq!(saved(|state| state.display = Display::new(32, 16, false)), Some(SaveStateError::InvalidValue("display size")));
+        assert_eq!(saved(|state| state.display.set_high_resolution(true)), None);
+    }
+}
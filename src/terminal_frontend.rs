@@ -0,0 +1,381 @@
+use chip8::debugger;
+use chip8::{Display, Emulator, EmulatorInput, EmulatorOutput};
+use crate::config::{Colors, Config, Rgb};
+use crate::movie_session::MovieSession;
+use crate::phosphor::Phosphor;
+use crate::recording::Recorder;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags};
+use crossterm::event::{PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// How long a key counts as held after it was pressed, in terminals that do not report key releases. Keys held down
+/// are repeated by the terminal more often than this, so they stay held after the initial repeat delay.
+const KEY_HOLD_DURATION: Duration = Duration::from_millis(200);
+
+/// The characters used to draw the display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Charset {
+    /// Half blocks, each character covering 1x2 pixels.
+    HalfBlocks,
+    /// Braille patterns, each character covering 2x4 pixels.
+    Braille,
+}
+
+impl Charset {
+    /// Renders a display of the given size as lines of text, given whether each pixel is lit.
+    pub fn render<F>(self, width: u8, height: u8, pixel_lit: F) -> Vec<String>
+    where
+        F: Fn(u8, u8) -> bool,
+    {
+        let lit = |x: u32, y: u32| x < width as u32 && y < height as u32 && pixel_lit(x as u8, y as u8);
+        let (cell_width, cell_height) = match self {
+            Charset::HalfBlocks => (1, 2),
+            Charset::Braille => (2, 4),
+        };
+        let columns = (width as u32).div_ceil(cell_width);
+        let rows = (height as u32).div_ceil(cell_height);
+        (0..rows)
+            .map(|row| {
+                let (y, mut line) = (row * cell_height, String::new());
+                for column in 0..columns {
+                    let x = column * cell_width;
+                    line.push(match self {
+                        Charset::HalfBlocks => match (lit(x, y), lit(x, y + 1)) {
+                            (false, false) => ' ',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (true, true) => '█',
+                        },
+                        Charset::Braille => {
+                            // Braille dots are numbered down the left column, then down the right, with the bottom row last.
+                            const DOTS: [(u32, u32); 8] = [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2), (0, 3), (1, 3)];
+                            let pattern = DOTS
+                                .iter()
+                                .enumerate()
+                                .filter(|&(_, &(dx, dy))| lit(x + dx, y + dy))
+                                .fold(0, |pattern, (dot, _)| pattern | 1 << dot);
+                            char::from_u32(0x2800 + pattern).expect("Invalid braille pattern.")
+                        },
+                    });
+                }
+                line
+            })
+            .collect()
+    }
+}
+
+/// Puts the terminal into raw mode on an alternate screen, restoring it when dropped.
+struct Terminal {
+    /// Whether the terminal reports key releases, rather than only presses and repeats.
+    reports_key_releases: bool,
+    enhanced_keyboard: bool,
+}
+
+impl Terminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        let enhanced_keyboard = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced_keyboard {
+            execute!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(Self { reports_key_releases: enhanced_keyboard || cfg!(windows), enhanced_keyboard })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.enhanced_keyboard {
+            let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[derive(Clone, Copy)]
+enum KeyHold {
+    Released,
+    Held,
+    HeldUntil(Instant),
+}
+
+/// Keys that can be bound to CHIP-8 keys, by the names used in the config file.
+fn key_code(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(char), None) => Some(KeyCode::Char(char.to_ascii_lowercase())),
+        _ => match name {
+            "Space" => Some(KeyCode::Char(' ')),
+            "Return" => Some(KeyCode::Enter),
+            "Tab" => Some(KeyCode::Tab),
+            "Backspace" => Some(KeyCode::Backspace),
+            "Up" => Some(KeyCode::Up),
+            "Down" => Some(KeyCode::Down),
+            "Left" => Some(KeyCode::Left),
+            "Right" => Some(KeyCode::Right),
+            _ => None,
+        },
+    }
+}
+
+fn key_bindings(config: &Config) -> Result<HashMap<KeyCode, u8>, String> {
+    let mut key_bindings = HashMap::new();
+    for (key, name) in config.keys.iter().enumerate() {
+        let key_code = key_code(name).ok_or_else(|| format!("Unknown terminal key: {}.", name))?;
+        key_bindings.insert(key_code, key as u8);
+    }
+    Ok(key_bindings)
+}
+
+fn color(Rgb(r, g, b): Rgb) -> Color {
+    Color::Rgb { r, g, b }
+}
+
+/// The palettes that look different in the terminal, which only uses the background and foreground colors.
+fn terminal_palettes(palettes: Vec<Colors>) -> Vec<Colors> {
+    let mut shown = vec![];
+    for colors in palettes {
+        if !shown.iter().any(|other: &Colors| (other.background, other.foreground) == (colors.background, colors.foreground)) {
+            shown.push(colors);
+        }
+    }
+    shown
+}
+
+/// Runs the emulator in the terminal, drawing the display with the given characters, until Escape is pressed.
+pub fn run(config: Config, mut emulator: Emulator, mut recorder: Recorder, mut movie: Option<MovieSession>, charset: Charset) -> Result<(), String> {
+    let key_bindings = key_bindings(&config)?;
+    let terminal = Terminal::enter().map_err(|error| format!("Could not set up the terminal: {}.", error))?;
+    let phosphor = Phosphor::new(config.persistence, config.decay_frames);
+    let mut screen = Screen { palettes: terminal_palettes(config.palettes()), palette: 0, charset, phosphor, display_version: None, lines: vec![] };
+
+    let mut keys = [KeyHold::Released; 16];
+    let mut input = EmulatorInput::new();
+    let mut frame = 0;
+    let mut muted = false;
+    let mut sound_playing = false;
+    let mut error = None;
+    // Errors are only reported once the terminal is restored, as they would garble the screen.
+    let mut recording_error = None;
+    let mut quit = false;
+    let frame_rate = emulator.settings().frame_rate;
+    emulator.execute(|display, output| {
+        let mut render = || -> Result<EmulatorInput, String> {
+            if frame > 0 {
+                recording_error = recorder.record(display, frame_rate).err().or(recording_error.take());
+            }
+            screen.draw(display, &output, "Esc: quit, M: mute, F9: palette")?;
+            if output.sound_playing && !sound_playing && !muted {
+                screen.bell()?;
+            }
+            sound_playing = output.sound_playing;
+
+            let now = Instant::now();
+            for event in poll_events()? {
+                match event {
+                    Event::Key(key_event) if key_event.kind != KeyEventKind::Release => {
+                        let ctrl_c = key_event.code == KeyCode::Char('c') && key_event.modifiers.contains(KeyModifiers::CONTROL);
+                        if key_event.code == KeyCode::Esc || ctrl_c {
+                            quit = true;
+                            return Ok(EmulatorInput::quit());
+                        }
+                        match key_bindings.get(&lowercase(key_event.code)) {
+                            Some(&key) if terminal.reports_key_releases => keys[key as usize] = KeyHold::Held,
+                            Some(&key) => keys[key as usize] = KeyHold::HeldUntil(now + KEY_HOLD_DURATION),
+                            None if lowercase(key_event.code) == KeyCode::Char('m') && key_event.kind == KeyEventKind::Press => muted = !muted,
+                            None if key_event.code == KeyCode::F(9) && key_event.kind == KeyEventKind::Press => screen.next_palette()?,
+                            None => {},
+                        }
+                    },
+                    Event::Key(key_event) => {
+                        if let Some(&key) = key_bindings.get(&lowercase(key_event.code)) {
+                            keys[key as usize] = KeyHold::Released;
+                        }
+                    },
+                    Event::Resize(..) => screen.clear()?,
+                    _ => {},
+                }
+            }
+
+            input.keypad.release_all_keys();
+            for (key, hold) in keys.iter().enumerate() {
+                match hold {
+                    KeyHold::Held => input.keypad.set_key_pressed(key as u8),
+                    KeyHold::HeldUntil(until) if *until > now => input.keypad.set_key_pressed(key as u8),
+                    _ => {},
+                }
+            }
+            if let Some(movie) = &mut movie {
+                movie.next_frame(frame, &mut input.keypad);
+            }
+            frame += 1;
+            Ok(input.clone())
+        };
+        render().unwrap_or_else(|render_error| {
+            error = Some(render_error);
+            EmulatorInput::quit()
+        })
+    });
+    if let Some(error) = error {
+        return Err(error);
+    }
+
+    // The emulator stops on its own once the program halts, so the final frame is shown until Escape is pressed.
+    let output = emulator.output();
+    if !quit {
+        recording_error = recorder.record(emulator.display(), frame_rate).err().or(recording_error);
+        let status = match output.fault {
+            Some(fault) => format!("Halted: {} Press Esc to quit.", fault),
+            None => "Program exited. Press Esc to quit.".to_string(),
+        };
+        screen.draw(emulator.display(), &output, &status)?;
+        while !poll_events()?.iter().any(|event| matches!(event, Event::Key(key_event) if key_event.code == KeyCode::Esc)) {}
+    }
+    drop(terminal);
+
+    if let Some(error) = recording_error {
+        eprintln!("{}", error);
+    }
+    if let Some(fault) = output.fault {
+        eprintln!("Program halted by fault: {}\n{}", fault, debugger::stack(emulator.state()));
+    }
+    for path in recorder.finish()? {
+        eprintln!("Saved recording to {}.", path.display());
+    }
+    if let Some(path) = movie.map(MovieSession::finish).transpose()?.flatten() {
+        eprintln!("Saved movie to {}.", path.display());
+    }
+    Ok(())
+}
+
+/// Letters are bound by their lowercase names, regardless of Shift or Caps Lock.
+fn lowercase(key_code: KeyCode) -> KeyCode {
+    match key_code {
+        KeyCode::Char(char) => KeyCode::Char(char.to_ascii_lowercase()),
+        _ => key_code,
+    }
+}
+
+/// Reads the pending terminal events, waiting briefly for one if there are none, so that waiting loops do not spin.
+fn poll_events() -> Result<Vec<Event>, String> {
+    let read = || -> io::Result<Vec<Event>> {
+        let mut events = vec![];
+        let mut timeout = Duration::from_millis(1);
+        while event::poll(timeout)? {
+            events.push(event::read()?);
+            timeout = Duration::ZERO;
+        }
+        Ok(events)
+    };
+    read().map_err(|error| format!("Could not read from the terminal: {}.", error))
+}
+
+/// Draws the display, only updating the lines that changed since the last frame.
+struct Screen {
+    /// The palettes F9 cycles through, which differ in their background or foreground colors.
+    palettes: Vec<Colors>,
+    palette: usize,
+    charset: Charset,
+    /// Keeps erased pixels lit while they linger, as the terminal cannot show them fading.
+    phosphor: Phosphor,
+    /// The version of the display currently on screen.
+    display_version: Option<u64>,
+    /// The lines currently on screen, along with the status line.
+    lines: Vec<String>,
+}
+
+impl Screen {
+    fn draw(&mut self, display: &Display, output: &EmulatorOutput, status: &str) -> Result<(), String> {
+        let display_version = output.display_version;
+        let lingering = self.phosphor.enabled() && !self.phosphor.update(display, output).is_empty();
+        if self.display_version == Some(display_version) && !lingering && self.lines.last().is_some_and(|line| line == status) {
+            return Ok(());
+        }
+        let mut lines = if self.phosphor.enabled() {
+            self.charset.render(display.width, display.height, |x, y| self.phosphor.level(x, y) > 0)
+        } else {
+            self.charset.render(display.width, display.height, |x, y| display.pixel(x, y) != 0)
+        };
+        lines.push(status.to_string());
+        let mut stdout = io::stdout();
+        let mut draw = || -> io::Result<()> {
+            if lines.len() != self.lines.len() {
+                queue!(stdout, ResetColor, Clear(ClearType::All))?;
+                self.lines.clear();
+            }
+            let (display_lines, status_line) = lines.split_at(lines.len() - 1);
+            let mut changed_rows = display_lines.iter().enumerate().filter(|&(row, line)| self.lines.get(row) != Some(line)).peekable();
+            if changed_rows.peek().is_some() {
+                let colors = self.palettes[self.palette];
+                queue!(stdout, SetForegroundColor(color(colors.foreground)), SetBackgroundColor(color(colors.background)))?;
+            }
+            for (row, line) in changed_rows {
+                queue!(stdout, MoveTo(0, row as u16), Print(line))?;
+            }
+            if self.lines.last() != status_line.last() {
+                queue!(stdout, ResetColor, MoveTo(0, display_lines.len() as u16), Clear(ClearType::CurrentLine), Print(&status_line[0]))?;
+            }
+            stdout.flush()
+        };
+        draw().map_err(|error| format!("Could not draw to the terminal: {}.", error))?;
+        self.display_version = Some(display_version);
+        self.lines = lines;
+        Ok(())
+    }
+
+    /// Forgets what is on screen, so that the next frame is drawn in full.
+    fn clear(&mut self) -> Result<(), String> {
+        self.display_version = None;
+        self.lines.clear();
+        execute!(io::stdout(), ResetColor, Clear(ClearType::All)).map_err(|error| format!("Could not draw to the terminal: {}.", error))
+    }
+
+    /// Switches to the next palette, redrawing the display in its colors.
+    fn next_palette(&mut self) -> Result<(), String> {
+        self.palette = (self.palette + 1) % self.palettes.len();
+        self.clear()
+    }
+
+    /// Rings the terminal bell, which is how the terminal plays sound.
+    fn bell(&self) -> Result<(), String> {
+        execute!(io::stdout(), Print('\x07')).map_err(|error| format!("Could not write to the terminal: {}.", error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut display = Display::new(4, 4, false);
+        display.apply_sprite(0, 0, &[0x80, 0x40, 0xC0, 0x10]);
+        let lit = |x, y| display.pixel(x, y) != 0;
+        assert_eq!(Charset::HalfBlocks.render(4, 4, lit), vec!["▀▄  ", "▀▀ ▄"]);
+        assert_eq!(Charset::Braille.render(4, 4, lit), vec!["\u{2835}\u{2880}"]);
+    }
+
+    #[test]
+    fn test_key_code() {
+        assert_eq!(key_code("Q"), Some(KeyCode::Char('q')));
+        assert_eq!(key_code("Space"), Some(KeyCode::Char(' ')));
+        assert_eq!(key_code("Keypad 5"), None);
+    }
+
+    #[test]
+    fn test_terminal_palettes() {
+        let colors = Colors { background: Rgb(0, 0, 0), foreground: Rgb(255, 255, 255), foreground2: Rgb(255, 0, 0), blend: Rgb(0, 255, 0) };
+        let other_planes = Colors { foreground2: Rgb(0, 0, 255), blend: Rgb(0, 0, 255), ..colors };
+        let other_foreground = Colors { foreground: Rgb(0, 255, 0), ..colors };
+        assert_eq!(terminal_palettes(vec![colors, other_planes, other_foreground]), vec![colors, other_foreground]);
+    }
+}